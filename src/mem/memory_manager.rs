@@ -41,6 +41,30 @@ impl<'a> MemoryManager<'a> {
     }
 }
 
+impl<'a> MemoryManager<'a> {
+    /// Make sure every size class has a prefaulted spare chunk ready.  Returns false if any pool could not prepare one.
+    pub fn prepare_spare_chunks(&self) -> bool {
+        let mut result = self.pool_64.prepare_spare_chunk();
+        result &= self.pool_128.prepare_spare_chunk();
+        result &= self.pool_256.prepare_spare_chunk();
+        result &= self.pool_512.prepare_spare_chunk();
+        result &= self.pool_1024.prepare_spare_chunk();
+        result &= self.pool_2048.prepare_spare_chunk();
+        return result;
+    }
+
+    /// `mlock` every chunk mapped so far, in every size class.
+    pub fn lock_memory(&self) -> bool {
+        let mut result = self.pool_64.lock_memory();
+        result &= self.pool_128.lock_memory();
+        result &= self.pool_256.lock_memory();
+        result &= self.pool_512.lock_memory();
+        result &= self.pool_1024.lock_memory();
+        result &= self.pool_2048.lock_memory();
+        return result;
+    }
+}
+
 // This function is a super duper bad idea
 impl<'a> MemoryManager<'a> {
     // unsafe fn clear(&self){
@@ -1,35 +1,325 @@
 use crate::mem::mmap;
 use crate::mem::QueueUsize;
+use crate::sync::RWSpinLock;
 use crate::sync::Spinlock;
 use core::marker::PhantomData;
 use core::num::NonZeroUsize;
 use core::ptr;
+#[cfg(debug_assertions)]
+use core::sync::atomic::AtomicPtr;
+use core::sync::atomic::AtomicU32;
+#[cfg(debug_assertions)]
+use core::sync::atomic::AtomicU64;
 use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::Ordering;
 
 pub const MAX_CHUNKS_POT: usize = 10;
 pub const MAX_CHUNKS: usize = 1 << MAX_CHUNKS_POT;
+/// How many blocks the batch calls move through the free queue per lock.
+const BATCH_SIZE: usize = 64;
+/// Debug builds tag every block with the generation it was last allocated or freed in.  This bit marks a block as allocated.
+#[cfg(debug_assertions)]
+const TAG_LIVE: u64 = 1 << 32;
+
+/// Controls how a MemoryPool carves its address space into chunks.
+/// The first chunk holds `initial_chunk_blocks` blocks, and each following chunk is `growth_factor` times larger, up to `max_chunk_blocks`.
+/// A growth factor of 1 gives fixed size chunks.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct MemoryPoolPolicy {
+    initial_chunk_blocks: usize,
+    growth_factor: usize,
+    max_chunk_blocks: usize,
+    preallocate_chunks: usize,
+    max_footprint: usize,
+    populate: bool,
+    lock_memory: bool,
+}
+
+impl MemoryPoolPolicy {
+    /// Fixed size chunks of `chunk_blocks` blocks, with no preallocation and no footprint limit.
+    pub const fn new(chunk_blocks: usize) -> MemoryPoolPolicy {
+        return MemoryPoolPolicy {
+            initial_chunk_blocks: chunk_blocks,
+            growth_factor: 1,
+            max_chunk_blocks: chunk_blocks,
+            preallocate_chunks: 0,
+            max_footprint: usize::MAX,
+            populate: false,
+            lock_memory: false,
+        };
+    }
+
+    /// Grow geometrically - each chunk is `growth_factor` times the size of the last, up to `max_chunk_blocks` blocks.
+    pub const fn with_growth(
+        self,
+        growth_factor: usize,
+        max_chunk_blocks: usize,
+    ) -> MemoryPoolPolicy {
+        return MemoryPoolPolicy {
+            growth_factor: growth_factor,
+            max_chunk_blocks: max_chunk_blocks,
+            ..self
+        };
+    }
+
+    /// Map this many chunks when `MemoryPool::preallocate` is called, instead of waiting for them to be needed.
+    pub const fn with_preallocation(self, chunks: usize) -> MemoryPoolPolicy {
+        return MemoryPoolPolicy {
+            preallocate_chunks: chunks,
+            ..self
+        };
+    }
+
+    /// Never map more than this many bytes of chunks.  Allocation fails rather than exceed it.
+    pub const fn with_max_footprint(self, bytes: usize) -> MemoryPoolPolicy {
+        return MemoryPoolPolicy {
+            max_footprint: bytes,
+            ..self
+        };
+    }
+
+    /// Map chunks with `MAP_POPULATE`, so they are faulted in when mapped rather than on first touch.
+    pub const fn with_populate(self, populate: bool) -> MemoryPoolPolicy {
+        return MemoryPoolPolicy {
+            populate: populate,
+            ..self
+        };
+    }
+
+    /// `mlock` every chunk as it is mapped, so it can never be paged out.  Chunks that cannot be locked are still used.
+    pub const fn with_locked_memory(self, lock_memory: bool) -> MemoryPoolPolicy {
+        return MemoryPoolPolicy {
+            lock_memory: lock_memory,
+            ..self
+        };
+    }
+
+    /// How many blocks each chunk holds.  Each chunk's count is worked out from the last, once, when the pool is built.
+    const fn chunk_blocks(&self) -> [u32; MAX_CHUNKS] {
+        let mut table = [0; MAX_CHUNKS];
+        let mut blocks = self.initial_chunk_blocks;
+        let mut i = 0;
+        while i < MAX_CHUNKS {
+            let mut clamped = blocks;
+            if clamped > self.max_chunk_blocks {
+                clamped = self.max_chunk_blocks;
+            }
+            if clamped > BaseMemoryPool::MAX_BLOCKS {
+                clamped = BaseMemoryPool::MAX_BLOCKS;
+            }
+            if clamped == 0 {
+                clamped = 1;
+            }
+            table[i] = clamped as u32;
+            if self.growth_factor > 1 && blocks < self.max_chunk_blocks {
+                blocks = blocks.saturating_mul(self.growth_factor);
+            }
+            i += 1;
+        }
+        return table;
+    }
+}
+
+struct ChunkTable {
+    chunks: [mmap::MapAlloc; MAX_CHUNKS],
+    footprint: usize,
+}
+
+/// Debug builds only - where a chunk starts, and its block tags, one AtomicU64 per block mapped alongside the chunk.  Not counted in the footprint.
+/// Published once the chunk is mapped, so a block's tag can be found without taking the chunk lock.
+#[cfg(debug_assertions)]
+struct DebugChunk {
+    start: AtomicUsize,
+    tags: AtomicPtr<AtomicU64>,
+}
+
+#[cfg(debug_assertions)]
+impl DebugChunk {
+    const fn new() -> DebugChunk {
+        return DebugChunk {
+            start: AtomicUsize::new(0),
+            tags: AtomicPtr::new(ptr::null_mut()),
+        };
+    }
+}
 
 struct BaseMemoryPool {
-    active_chunk_remaining_free: Spinlock<[mmap::MapAlloc; MAX_CHUNKS]>,
+    // Chunks at or beyond the active count may already be mapped, ready to be used.
+    active_chunk_remaining_free: Spinlock<ChunkTable>,
     block_size: usize,
-    block_count: usize,
+    policy: MemoryPoolPolicy,
+    chunk_blocks: [u32; MAX_CHUNKS],
+    #[cfg(debug_assertions)]
+    debug_chunks: [DebugChunk; MAX_CHUNKS],
+    // One past the highest chunk published in debug_chunks.
+    #[cfg(debug_assertions)]
+    debug_chunk_count: AtomicUsize,
 }
 impl BaseMemoryPool {
     const MAX_BLOCKS: usize = 65536;
-    const BLOCK_MASK: u32 = (BaseMemoryPool::MAX_BLOCKS - 1) as u32;
     const CHUNK_SHIFT: usize = 17;
+    const BLOCK_MASK: u32 = (1 << BaseMemoryPool::CHUNK_SHIFT) - 1;
     // const MAX_CHUNKS: usize = 1024;
 
-    const fn new(block_size: usize, block_count: usize) -> BaseMemoryPool {
+    const fn new(block_size: usize, policy: MemoryPoolPolicy) -> BaseMemoryPool {
         // assert!(block_size.is_power_of_two());
-        // assert!(block_count.is_power_of_two() && block_count <= BaseMemoryPool::MAX_BLOCKS);
         return BaseMemoryPool {
             block_size: block_size,
-            block_count: block_count,
-            active_chunk_remaining_free: Spinlock::new(0, [mmap::MapAlloc::null(); MAX_CHUNKS]),
+            policy: policy,
+            chunk_blocks: policy.chunk_blocks(),
+            #[cfg(debug_assertions)]
+            debug_chunks: [const { DebugChunk::new() }; MAX_CHUNKS],
+            #[cfg(debug_assertions)]
+            debug_chunk_count: AtomicUsize::new(0),
+            active_chunk_remaining_free: Spinlock::new(
+                0,
+                ChunkTable {
+                    chunks: [mmap::MapAlloc::null(); MAX_CHUNKS],
+                    footprint: 0,
+                },
+            ),
         };
     }
 
+    /// Map the chunk at `index` into the table, if it is not mapped already.  Must be called with the lock held.
+    fn map_chunk(&self, table: &mut ChunkTable, index: usize) -> bool {
+        if !table.chunks[index].is_null() {
+            return true;
+        }
+        let page_aligned_size = self.chunk_size(index);
+        if page_aligned_size > self.policy.max_footprint - table.footprint {
+            return false;
+        }
+        let mem = self.alloc_chunk(page_aligned_size);
+        // Allocation failed.  This must abort.
+        if mem.is_null() {
+            return false;
+        }
+        #[cfg(debug_assertions)]
+        {
+            if !self.publish_debug_chunk(index, mem) {
+                unsafe {
+                    mmap::free_page_aligned(mem.memory, mem.size);
+                }
+                return false;
+            }
+        }
+        table.chunks[index] = mem;
+        table.footprint += mem.size;
+        return true;
+    }
+
+    #[cfg(debug_assertions)]
+    fn debug_tags_size(&self, index: usize) -> usize {
+        return mmap::get_page_aligned_size(
+            self.chunk_blocks(index) * core::mem::size_of::<AtomicU64>(),
+        );
+    }
+
+    /// Map the tags for a newly mapped chunk, and publish both.  Must be called with the lock held.
+    /// Returns false if the tags could not be mapped, in which case the chunk must not be used either.
+    #[cfg(debug_assertions)]
+    fn publish_debug_chunk(&self, index: usize, mem: mmap::MapAlloc) -> bool {
+        let tags = mmap::alloc_page_aligned(self.debug_tags_size(index));
+        if tags.is_null() {
+            return false;
+        }
+        let chunk = &self.debug_chunks[index];
+        chunk
+            .tags
+            .store(tags.memory as *mut AtomicU64, Ordering::Relaxed);
+        chunk.start.store(mem.memory as usize, Ordering::Release);
+        self.debug_chunk_count
+            .fetch_max(index + 1, Ordering::Release);
+        return true;
+    }
+
+    #[inline(always)]
+    fn chunk_blocks(&self, index: usize) -> usize {
+        return self.chunk_blocks[index] as usize;
+    }
+
+    #[inline(always)]
+    fn chunk_size(&self, index: usize) -> usize {
+        return mmap::get_page_aligned_size(self.chunk_blocks(index) * self.block_size);
+    }
+
+    fn alloc_chunk(&self, page_aligned_size: usize) -> mmap::MapAlloc {
+        let mem = mmap::alloc_page_aligned_populate(page_aligned_size, self.policy.populate);
+        if !mem.is_null() && self.policy.lock_memory {
+            unsafe {
+                mmap::lock_page_aligned(mem.memory, mem.size);
+            }
+        }
+        return mem;
+    }
+
+    /// Make sure the chunk after the active one is mapped and faulted in.  The slow part happens without the lock held, so allocation is never stalled by it.
+    fn prepare_spare_chunk(&self) -> bool {
+        let index = {
+            let active_chunk_lock = self.active_chunk_remaining_free.lock();
+            let index = (active_chunk_lock.read() >> BaseMemoryPool::CHUNK_SHIFT) as usize;
+            if index >= MAX_CHUNKS {
+                return false;
+            }
+            if !active_chunk_lock.chunks[index].is_null() {
+                return true;
+            }
+            if self.chunk_size(index) > self.policy.max_footprint - active_chunk_lock.footprint {
+                return false;
+            }
+            index
+        };
+
+        let mem = self.alloc_chunk(self.chunk_size(index));
+        if mem.is_null() {
+            return false;
+        }
+        if !self.policy.populate {
+            unsafe {
+                mmap::prefault_page_aligned(mem.memory, mem.size);
+            }
+        }
+
+        let mut active_chunk_lock = self.active_chunk_remaining_free.lock();
+        let chunk_count = (active_chunk_lock.read() >> BaseMemoryPool::CHUNK_SHIFT) as usize;
+        // The allocator may have needed this slot while we were faulting, and mapped its own.
+        if index < chunk_count
+            || !active_chunk_lock.chunks[index].is_null()
+            || mem.size > self.policy.max_footprint - active_chunk_lock.footprint
+        {
+            unsafe {
+                mmap::free_page_aligned(mem.memory, mem.size);
+            }
+            return false;
+        }
+        #[cfg(debug_assertions)]
+        {
+            if !self.publish_debug_chunk(index, mem) {
+                unsafe {
+                    mmap::free_page_aligned(mem.memory, mem.size);
+                }
+                return false;
+            }
+        }
+        active_chunk_lock.chunks[index] = mem;
+        active_chunk_lock.footprint += mem.size;
+        return true;
+    }
+
+    /// `mlock` every chunk mapped so far.
+    fn lock_memory(&self) -> bool {
+        let active_chunk_lock = self.active_chunk_remaining_free.lock();
+        let mut result = true;
+        for i in 0..MAX_CHUNKS {
+            let chunk = active_chunk_lock.chunks[i];
+            if !chunk.is_null() {
+                result &= unsafe { mmap::lock_page_aligned(chunk.memory, chunk.size) };
+            }
+        }
+        return result;
+    }
+
     fn get_free_block(&self) -> *mut u8 {
         let mut active_chunk_lock = self.active_chunk_remaining_free.lock();
 
@@ -46,27 +336,19 @@ impl BaseMemoryPool {
                 return ptr::null_mut();
                 // //handle_alloc_error
             }
-            let page_aligned_size = mmap::get_page_aligned_size(self.block_count * self.block_size);
-            // println!("chunks  {} {} {}", chunk_count, remaining_blocks, page_aligned_size);
-            let mem = mmap::alloc_page_aligned(page_aligned_size);
-            // println!("mem  {}", mem.memory as usize);
-            // Allocation failed.  This must abort.
-            if mem.is_null() {
+            if !self.map_chunk(&mut active_chunk_lock, chunk_count as usize) {
                 // core::panic!("Memory Allocation Failed.");
                 return ptr::null_mut();
                 //process::abort();
-                //
             }
-
-            (*active_chunk_lock)[chunk_count as usize] = mem;
-            remaining_blocks = self.block_count as u32;
+            remaining_blocks = self.chunk_blocks[chunk_count as usize];
             chunk_count += 1;
         }
         let new_remaining_blocks = remaining_blocks - 1;
 
         let address = unsafe {
-            (*active_chunk_lock)[(chunk_count - 1) as usize]
-                .get_unchecked((new_remaining_blocks * self.block_size as u32) as isize)
+            active_chunk_lock.chunks[(chunk_count - 1) as usize]
+                .get_unchecked((new_remaining_blocks as usize * self.block_size) as isize)
         };
 
         active_chunk_lock
@@ -75,20 +357,80 @@ impl BaseMemoryPool {
         return address;
     }
 
+    /// Map the policy's preallocated chunks ahead of the active chunk.
+    fn preallocate(&self) -> bool {
+        let mut active_chunk_lock = self.active_chunk_remaining_free.lock();
+        let chunk_count = (active_chunk_lock.read() >> BaseMemoryPool::CHUNK_SHIFT) as usize;
+        let mut end = chunk_count + self.policy.preallocate_chunks;
+        if end > MAX_CHUNKS {
+            end = MAX_CHUNKS;
+        }
+        for i in chunk_count..end {
+            if !self.map_chunk(&mut active_chunk_lock, i) {
+                return false;
+            }
+        }
+        return true;
+    }
+
+    fn footprint(&self) -> usize {
+        return self.active_chunk_remaining_free.lock().footprint;
+    }
+
+    /// The debug tag of the block at ptr, or None if ptr is not a block in any mapped chunk.
+    /// Chunks and their tags stay mapped until `clear`, so this needs no lock.
+    #[cfg(debug_assertions)]
+    fn block_tag(&self, ptr: *mut u8) -> Option<&AtomicU64> {
+        let address = ptr as usize;
+        let count = self.debug_chunk_count.load(Ordering::Acquire);
+        for i in 0..count {
+            let chunk = &self.debug_chunks[i];
+            let start = chunk.start.load(Ordering::Acquire);
+            if start == 0
+                || address < start
+                || address >= start + self.chunk_blocks(i) * self.block_size
+            {
+                continue;
+            }
+            let index = (address - start) / self.block_size;
+            if start + index * self.block_size != address {
+                return None;
+            }
+            let tags = chunk.tags.load(Ordering::Relaxed);
+            return Some(unsafe { &*tags.add(index) });
+        }
+        return None;
+    }
+
+    /// Start handing out blocks from the first chunk again, keeping every chunk mapped.
+    fn reset(&self) {
+        let mut active_chunk_lock = self.active_chunk_remaining_free.lock();
+        active_chunk_lock.write(0);
+    }
+
     fn clear(&self) {
         unsafe {
             let mut active_chunk_lock = self.active_chunk_remaining_free.lock();
-            let active_chunk_data = active_chunk_lock.read();
 
-            // Decompose the atomic value
-            let chunk_count = active_chunk_data >> BaseMemoryPool::CHUNK_SHIFT;
-            for i in 0..chunk_count {
-                mmap::free_page_aligned(
-                    (*active_chunk_lock)[i as usize].memory,
-                    (*active_chunk_lock)[i as usize].size,
-                );
-                (*active_chunk_lock)[i as usize] = mmap::MapAlloc::null();
+            // Preallocated chunks may be mapped beyond the active count, so check every slot.
+            for i in 0..MAX_CHUNKS {
+                let chunk = active_chunk_lock.chunks[i];
+                if !chunk.is_null() {
+                    mmap::free_page_aligned(chunk.memory, chunk.size);
+                    active_chunk_lock.chunks[i] = mmap::MapAlloc::null();
+                }
+                #[cfg(debug_assertions)]
+                {
+                    let chunk = &self.debug_chunks[i];
+                    if chunk.start.swap(0, Ordering::Relaxed) != 0 {
+                        let tags = chunk.tags.swap(ptr::null_mut(), Ordering::Relaxed);
+                        mmap::free_page_aligned(tags as *mut u8, self.debug_tags_size(i));
+                    }
+                }
             }
+            #[cfg(debug_assertions)]
+            self.debug_chunk_count.store(0, Ordering::Relaxed);
+            active_chunk_lock.footprint = 0;
             active_chunk_lock.write(0);
         }
     }
@@ -102,9 +444,18 @@ impl Drop for BaseMemoryPool {
     }
 }
 
+/// Identifies the generation of a MemoryPool a block was allocated in.  `MemoryPool::reset` starts a new generation.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+pub struct PoolScope {
+    generation: u32,
+}
+
 pub struct MemoryPool<'a> {
     memory_pool: BaseMemoryPool,
     free_queue: QueueUsize<'a>,
+    generation: AtomicU32,
+    // Scoped allocations and frees hold this for reading, so a reset cannot land between their generation check and the free queue.
+    reset_lock: RWSpinLock<()>,
     _lifetime: PhantomData<&'a AtomicUsize>,
 }
 
@@ -124,40 +475,283 @@ impl<'a> MemoryPool<'a> {
         // assert!(is_power_of_two_or_zero(capacity));
         // assert!(block_size != 0);
         // assert!(capacity >= MAX_CHUNKS);
+        return MemoryPool::from_static_with_policy(
+            block_size,
+            slice,
+            capacity,
+            MemoryPoolPolicy::new(capacity >> MAX_CHUNKS_POT),
+        );
+    }
+
+    /// Like `from_static`, but chunk sizes come from the policy rather than being capacity / MAX_CHUNKS.
+    /// Capacity is the size of the free queue, and should be at least the number of blocks the policy can ever map - frees beyond it are dropped.
+    pub const unsafe fn from_static_with_policy(
+        block_size: usize,
+        slice: &'a *mut AtomicUsize,
+        capacity: usize,
+        policy: MemoryPoolPolicy,
+    ) -> MemoryPool<'a> {
         return MemoryPool {
-            memory_pool: BaseMemoryPool::new(block_size, capacity >> MAX_CHUNKS_POT),
+            memory_pool: BaseMemoryPool::new(block_size, policy),
             free_queue: QueueUsize::from_static(slice, capacity),
+            generation: AtomicU32::new(0),
+            reset_lock: RWSpinLock::new(()),
             _lifetime: PhantomData,
         };
     }
 
+    /// Map the chunks the policy asked to preallocate.  Call this at startup - the constructor is const, and cannot map memory itself.
+    /// Returns false if the footprint limit or the system stopped it short.
+    pub fn preallocate(&self) -> bool {
+        return self.memory_pool.preallocate();
+    }
+
+    /// Total bytes of chunks currently mapped.
+    pub fn footprint(&self) -> usize {
+        return self.memory_pool.footprint();
+    }
+
+    /// Size of each block in bytes.
+    #[inline(always)]
+    pub const fn block_size(&self) -> usize {
+        return self.memory_pool.block_size;
+    }
+
+    /// How many freed blocks are waiting in the free queue to be handed out again.  A snapshot, for statistics and debugging.
+    pub fn free_count(&self) -> usize {
+        return self.free_queue.len();
+    }
+
+    /// Map and fault in the next chunk before the pool needs it, so growing mid-frame does not take page faults.
+    /// Keeps at most one spare chunk - if one is already ready, this does nothing.  Intended to be called from a background thread, see `Prefaulter`.
+    /// Returns false if no spare chunk could be prepared.
+    pub fn prepare_spare_chunk(&self) -> bool {
+        return self.memory_pool.prepare_spare_chunk();
+    }
+
+    /// `mlock` every chunk mapped so far.  Use `MemoryPoolPolicy::with_locked_memory` to lock chunks mapped later, too.
+    /// Returns false if any chunk could not be locked.
+    pub fn lock_memory(&self) -> bool {
+        return self.memory_pool.lock_memory();
+    }
+
+    /// Debug builds only - mark a block as handed out in the current generation.
+    #[cfg(debug_assertions)]
+    fn tag_allocated(&self, ptr: *mut u8) {
+        if ptr.is_null() {
+            return;
+        }
+        let generation = self.generation.load(Ordering::Acquire) as u64;
+        if let Some(tag) = self.memory_pool.block_tag(ptr) {
+            let old = tag.swap(TAG_LIVE | generation, Ordering::Relaxed);
+            // Blocks still live from before a reset are legitimately handed out again.
+            debug_assert!(old != TAG_LIVE | generation, "block handed out twice");
+        }
+    }
+
+    /// Debug builds only - check a block being freed is live in the current generation, and mark it free.
+    #[cfg(debug_assertions)]
+    fn tag_freed(&self, ptr: *mut u8) {
+        let generation = self.generation.load(Ordering::Acquire) as u64;
+        let tag = match self.memory_pool.block_tag(ptr) {
+            Some(x) => x,
+            None => panic!("freed a pointer that is not a block of this pool"),
+        };
+        let old = tag.swap(generation, Ordering::Relaxed);
+        debug_assert!(
+            old == TAG_LIVE | generation,
+            "freed a block twice, or a block left over from before a reset"
+        );
+    }
+
     // #[inline(always)]
     pub unsafe fn allocate(&self) -> *mut u8 {
         //dequeue - if dequeue fails
         let result = self.free_queue.dequeue();
-        match result {
+        let block = match result {
             Some(x) => {
                 // println!("dequeue {} {}",x.get(), self.memory_pool.block_size);
-                return x.get() as *mut u8;
-            }
-            None => {
-                return self.memory_pool.get_free_block();
+                x.get() as *mut u8
             }
-        }
+            None => self.memory_pool.get_free_block(),
+        };
+        #[cfg(debug_assertions)]
+        self.tag_allocated(block);
+        return block;
     }
 
     /// This is unsafe, because if you pass back a bad pointer there is no checking.
+    /// Debug builds do check, and panic on a double free or on a block allocated before the last reset.
     #[inline(always)]
     pub unsafe fn deallocate(&self, ptr: *mut u8) {
         // println!("enqueue {} {}", ptr as usize, self.memory_pool.block_size);
+        #[cfg(debug_assertions)]
+        self.tag_freed(ptr);
         self.free_queue
             .enqueue(NonZeroUsize::new(ptr as usize).unwrap());
     }
 
+    /// Allocate up to `blocks.len()` blocks, returning how many were filled in from the front.
+    /// Recycled blocks are taken from the free queue in one go before new ones are handed out.
+    pub unsafe fn allocate_batch(&self, blocks: &mut [*mut u8]) -> usize {
+        let mut batch = [NonZeroUsize::MIN; BATCH_SIZE];
+        let mut count = 0;
+        while count < blocks.len() {
+            let wanted = core::cmp::min(BATCH_SIZE, blocks.len() - count);
+            let dequeued = self.free_queue.dequeue_batch(&mut batch[..wanted]);
+            for x in batch[..dequeued].iter() {
+                blocks[count] = x.get() as *mut u8;
+                #[cfg(debug_assertions)]
+                self.tag_allocated(blocks[count]);
+                count += 1;
+            }
+            if dequeued < wanted {
+                break;
+            }
+        }
+        while count < blocks.len() {
+            let block = self.memory_pool.get_free_block();
+            if block.is_null() {
+                break;
+            }
+            #[cfg(debug_assertions)]
+            self.tag_allocated(block);
+            blocks[count] = block;
+            count += 1;
+        }
+        return count;
+    }
+
+    /// Return many blocks at once, taking the free queue lock once per BATCH_SIZE blocks rather than once per block.
+    /// This is unsafe, because if you pass back a bad pointer there is no checking.
+    pub unsafe fn deallocate_batch(&self, blocks: &[*mut u8]) {
+        let mut batch = [NonZeroUsize::MIN; BATCH_SIZE];
+        for chunk in blocks.chunks(BATCH_SIZE) {
+            for (x, ptr) in batch.iter_mut().zip(chunk.iter()) {
+                #[cfg(debug_assertions)]
+                self.tag_freed(*ptr);
+                *x = NonZeroUsize::new(*ptr as usize).unwrap();
+            }
+            let enqueued = self.free_queue.enqueue_batch(&batch[..chunk.len()]);
+            debug_assert!(enqueued == chunk.len());
+        }
+    }
+
     pub unsafe fn clear(&self) {
+        self.generation.fetch_add(1, Ordering::AcqRel);
         self.free_queue.clear();
         self.memory_pool.clear();
     }
+
+    /// The current generation.
+    #[inline(always)]
+    pub fn scope(&self) -> PoolScope {
+        return PoolScope {
+            generation: self.generation.load(Ordering::Acquire),
+        };
+    }
+
+    /// Whether blocks allocated under this scope are still live, i.e. the pool has not been reset since.
+    #[inline(always)]
+    pub fn is_current(&self, scope: PoolScope) -> bool {
+        return self.generation.load(Ordering::Acquire) == scope.generation;
+    }
+
+    /// Allocate a block, along with the scope it belongs to.  A concurrent reset waits for this to finish, so the scope is always the block's.
+    pub unsafe fn allocate_scoped(&self) -> (*mut u8, PoolScope) {
+        let _lock = self.reset_lock.read();
+        let scope = self.scope();
+        return (self.allocate(), scope);
+    }
+
+    /// Free a block allocated with `allocate_scoped`.  If the pool was reset since, the block already belongs to the new generation's free space,
+    /// and it is ignored instead of being pushed onto the free queue a second time.  Returns false if the block was stale.
+    /// The check and the free happen under the reset lock, so a concurrent reset either comes first, and the block is stale, or waits until it is queued.
+    pub unsafe fn deallocate_scoped(&self, ptr: *mut u8, scope: PoolScope) -> bool {
+        let _lock = self.reset_lock.read();
+        if !self.is_current(scope) {
+            return false;
+        }
+        self.deallocate(ptr);
+        return true;
+    }
+
+    /// Returns the pointer if it belongs to the current generation, or None if it is left over from before a reset.
+    /// Debug builds panic if the block was not allocated under this scope, such as a stale pointer paired with a newer scope.
+    #[inline(always)]
+    pub fn get_scoped(&self, ptr: *mut u8, scope: PoolScope) -> Option<*mut u8> {
+        if !self.is_current(scope) {
+            return None;
+        }
+        #[cfg(debug_assertions)]
+        {
+            let tag = self.memory_pool.block_tag(ptr);
+            debug_assert!(
+                tag.map(|x| x.load(Ordering::Relaxed)) == Some(TAG_LIVE | scope.generation as u64),
+                "block is not live in this scope"
+            );
+        }
+        return Some(ptr);
+    }
+
+    /// Invalidate every outstanding block, and start allocating from the beginning of the pool again.
+    /// Unlike `clear`, the memory stays mapped, so stale pointers still point at valid (if reused) memory rather than unmapped pages.
+    /// Returns the scope of the new generation.
+    /// Blocks from the previous generation may then only be passed to `deallocate_scoped` or `get_scoped` with their old scope, where the generation check turns them away.
+    /// Freeing one with the unsafe `deallocate` instead corrupts the new generation - debug builds detect it.  Scoped calls may race with a reset, unscoped ones must not.
+    pub fn reset(&self) -> PoolScope {
+        let _lock = self.reset_lock.write();
+        let generation = self
+            .generation
+            .fetch_add(1, Ordering::AcqRel)
+            .wrapping_add(1);
+        self.free_queue.clear();
+        self.memory_pool.reset();
+        return PoolScope {
+            generation: generation,
+        };
+    }
+}
+
+/// A background thread that keeps memory pools topped up with a prefaulted spare chunk.
+/// The closure should call `MemoryPool::prepare_spare_chunk` (or `MemoryManager::prepare_spare_chunks`) on every pool it looks after.
+#[cfg(any(test, feature = "std"))]
+pub struct Prefaulter {
+    stop: std::sync::Arc<core::sync::atomic::AtomicBool>,
+    thread: Option<std::thread::JoinHandle<()>>,
+}
+
+#[cfg(any(test, feature = "std"))]
+impl Prefaulter {
+    /// Spawn the thread, calling `prepare` every `interval` until the Prefaulter is dropped.
+    pub fn spawn<F>(interval: std::time::Duration, prepare: F) -> Prefaulter
+    where
+        F: Fn() + Send + 'static,
+    {
+        let stop = std::sync::Arc::new(core::sync::atomic::AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let thread = std::thread::spawn(move || {
+            while !thread_stop.load(Ordering::Acquire) {
+                prepare();
+                std::thread::park_timeout(interval);
+            }
+        });
+        return Prefaulter {
+            stop: stop,
+            thread: Some(thread),
+        };
+    }
+}
+
+#[cfg(any(test, feature = "std"))]
+impl Drop for Prefaulter {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Release);
+        if let Some(thread) = self.thread.take() {
+            thread.thread().unpark();
+            let _ = thread.join();
+        }
+    }
 }
 
 #[cfg(test)]
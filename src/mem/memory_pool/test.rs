@@ -2,6 +2,10 @@
 mod test {
 
     use crate::mem::memory_pool::MemoryPool;
+    use crate::mem::MemoryPoolPolicy;
+    use crate::mem::Prefaulter;
+    use std::time::Duration;
+    use std::time::Instant;
     // use crate::mem::queue::Swap;
     // use crate::sync::index_lock::IndexSpinlock;
     use core::sync::atomic::AtomicUsize;
@@ -49,4 +53,303 @@ mod test {
             }
         }
     }
+
+    #[test]
+    fn growth() {
+        unsafe {
+            let mut buffer_local: [usize; 4096] = [0; 4096];
+            let buffer_ptr = &mut buffer_local[0] as *mut usize as *mut AtomicUsize;
+            // 64, 128, 256, 512, 512, ... blocks of 64 bytes.
+            let policy = MemoryPoolPolicy::new(64).with_growth(2, 512);
+            let mp = MemoryPool::from_static_with_policy(64, &buffer_ptr, 4096, policy);
+            assert_eq!(mp.footprint(), 0);
+            for _i in 0..64 {
+                assert_ne!(mp.allocate(), core::ptr::null_mut());
+            }
+            assert_eq!(mp.footprint(), 64 * 64);
+            for _i in 0..(128 + 256 + 512) {
+                assert_ne!(mp.allocate(), core::ptr::null_mut());
+            }
+            assert_eq!(mp.footprint(), (64 + 128 + 256 + 512) * 64);
+            assert_ne!(mp.allocate(), core::ptr::null_mut());
+            assert_eq!(mp.footprint(), (64 + 128 + 256 + 512 + 512) * 64);
+        }
+    }
+
+    #[test]
+    fn max_footprint() {
+        unsafe {
+            let mut buffer_local: [usize; 4096] = [0; 4096];
+            let buffer_ptr = &mut buffer_local[0] as *mut usize as *mut AtomicUsize;
+            let policy = MemoryPoolPolicy::new(64).with_max_footprint(64 * 64 * 4);
+            let mp = MemoryPool::from_static_with_policy(64, &buffer_ptr, 4096, policy);
+            let mut storage: [*mut u8; 256] = [core::ptr::null_mut(); 256];
+            for block in storage.iter_mut() {
+                *block = mp.allocate();
+                assert_ne!(*block, core::ptr::null_mut());
+            }
+            assert_eq!(mp.allocate(), core::ptr::null_mut());
+            assert_eq!(mp.footprint(), 64 * 64 * 4);
+
+            // Freed blocks are still available once the limit is reached.
+            mp.deallocate(storage[0]);
+            assert_eq!(mp.allocate(), storage[0]);
+        }
+    }
+
+    #[test]
+    fn preallocate() {
+        unsafe {
+            let mut buffer_local: [usize; 4096] = [0; 4096];
+            let buffer_ptr = &mut buffer_local[0] as *mut usize as *mut AtomicUsize;
+            let policy = MemoryPoolPolicy::new(64).with_preallocation(4);
+            let mp = MemoryPool::from_static_with_policy(64, &buffer_ptr, 4096, policy);
+            assert!(mp.preallocate());
+            assert_eq!(mp.footprint(), 64 * 64 * 4);
+            for _i in 0..256 {
+                assert_ne!(mp.allocate(), core::ptr::null_mut());
+            }
+            // The preallocated chunks were used, rather than new ones mapped.
+            assert_eq!(mp.footprint(), 64 * 64 * 4);
+            assert_ne!(mp.allocate(), core::ptr::null_mut());
+            assert_eq!(mp.footprint(), 64 * 64 * 5);
+
+            mp.clear();
+            assert_eq!(mp.footprint(), 0);
+        }
+    }
+
+    #[test]
+    fn reset() {
+        unsafe {
+            let mut buffer_local: [usize; 4096] = [0; 4096];
+            let buffer_ptr = &mut buffer_local[0] as *mut usize as *mut AtomicUsize;
+            let mp = MemoryPool::from_static(64, &buffer_ptr, 4096);
+
+            let mut storage: [*mut u8; 1024] = [core::ptr::null_mut(); 1024];
+            let mut scope = mp.scope();
+            for slot in storage.iter_mut() {
+                let (block, block_scope) = mp.allocate_scoped();
+                assert_ne!(block, core::ptr::null_mut());
+                assert_eq!(block_scope, scope);
+                *slot = block;
+            }
+            let footprint = mp.footprint();
+            for block in storage[..512].iter() {
+                assert!(mp.deallocate_scoped(*block, scope));
+            }
+
+            let new_scope = mp.reset();
+            assert_ne!(new_scope, scope);
+            assert!(!mp.is_current(scope));
+            assert_eq!(mp.get_scoped(storage[600], scope), None);
+
+            // Stale frees are rejected, rather than handing the same block out twice.
+            for block in storage[512..].iter() {
+                assert!(!mp.deallocate_scoped(*block, scope));
+            }
+            let mut seen = std::collections::HashSet::new();
+            scope = new_scope;
+            for _i in 0..1024 {
+                let (block, block_scope) = mp.allocate_scoped();
+                assert_eq!(block_scope, scope);
+                assert_eq!(mp.get_scoped(block, block_scope), Some(block));
+                assert!(seen.insert(block as usize));
+            }
+            // The chunks mapped by the previous generation were reused.
+            assert_eq!(mp.footprint(), footprint);
+        }
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "before a reset")]
+    fn reset_stale_free() {
+        unsafe {
+            let mut buffer_local: [usize; 4096] = [0; 4096];
+            let buffer_ptr = &mut buffer_local[0] as *mut usize as *mut AtomicUsize;
+            let mp = MemoryPool::from_static(64, &buffer_ptr, 4096);
+            let block = mp.allocate();
+            mp.reset();
+            mp.deallocate(block);
+        }
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "not a block of this pool")]
+    fn free_foreign_block() {
+        unsafe {
+            let mut buffer_local: [usize; 4096] = [0; 4096];
+            let buffer_ptr = &mut buffer_local[0] as *mut usize as *mut AtomicUsize;
+            let mp = MemoryPool::from_static(64, &buffer_ptr, 4096);
+            let mut other_local: [usize; 4096] = [0; 4096];
+            let other_ptr = &mut other_local[0] as *mut usize as *mut AtomicUsize;
+            let other = MemoryPool::from_static(64, &other_ptr, 4096);
+            mp.allocate();
+            mp.deallocate(other.allocate());
+        }
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "not live")]
+    fn reset_stale_get() {
+        unsafe {
+            let mut buffer_local: [usize; 4096] = [0; 4096];
+            let buffer_ptr = &mut buffer_local[0] as *mut usize as *mut AtomicUsize;
+            let mp = MemoryPool::from_static(64, &buffer_ptr, 4096);
+            let (block, _scope) = mp.allocate_scoped();
+            let scope = mp.reset();
+            mp.get_scoped(block, scope);
+        }
+    }
+
+    #[test]
+    fn reset_threads() {
+        unsafe {
+            let mut buffer_local: [usize; 4096] = [0; 4096];
+            let buffer_ptr = &mut buffer_local[0] as *mut usize as *mut AtomicUsize;
+            let mp = MemoryPool::from_static(64, &buffer_ptr, 4096);
+            std::thread::scope(|s| {
+                for _t in 0..4 {
+                    s.spawn(|| {
+                        for _i in 0..2000 {
+                            let (block, scope) = mp.allocate_scoped();
+                            std::thread::yield_now();
+                            mp.deallocate_scoped(block, scope);
+                        }
+                    });
+                }
+                for _i in 0..100 {
+                    mp.reset();
+                    std::thread::yield_now();
+                }
+            });
+            // No block was queued into a generation it did not belong to, so every block handed out now is distinct.
+            let mut seen = std::collections::HashSet::new();
+            for _i in 0..4096 {
+                let block = mp.allocate();
+                assert_ne!(block, core::ptr::null_mut());
+                assert!(seen.insert(block as usize));
+            }
+        }
+    }
+
+    #[test]
+    fn spare_chunk() {
+        unsafe {
+            let mut buffer_local: [usize; 4096] = [0; 4096];
+            let buffer_ptr = &mut buffer_local[0] as *mut usize as *mut AtomicUsize;
+            let mp = MemoryPool::from_static_with_policy(
+                64,
+                &buffer_ptr,
+                4096,
+                MemoryPoolPolicy::new(64),
+            );
+            assert!(mp.prepare_spare_chunk());
+            assert_eq!(mp.footprint(), 64 * 64);
+            // Only one spare is kept.
+            assert!(mp.prepare_spare_chunk());
+            assert_eq!(mp.footprint(), 64 * 64);
+
+            for _i in 0..64 {
+                assert_ne!(mp.allocate(), core::ptr::null_mut());
+            }
+            assert_eq!(mp.footprint(), 64 * 64);
+            assert!(mp.prepare_spare_chunk());
+            assert_eq!(mp.footprint(), 64 * 64 * 2);
+        }
+    }
+
+    #[test]
+    fn populate_lock() {
+        unsafe {
+            let mut buffer_local: [usize; 4096] = [0; 4096];
+            let buffer_ptr = &mut buffer_local[0] as *mut usize as *mut AtomicUsize;
+            let policy = MemoryPoolPolicy::new(64)
+                .with_populate(true)
+                .with_locked_memory(true);
+            let mp = MemoryPool::from_static_with_policy(64, &buffer_ptr, 4096, policy);
+            for _i in 0..256 {
+                let block = mp.allocate();
+                assert_ne!(block, core::ptr::null_mut());
+                *block = 1;
+            }
+            // Whether this succeeds depends on RLIMIT_MEMLOCK - it just must not break the pool.
+            mp.lock_memory();
+            assert_eq!(mp.footprint(), 64 * 64 * 4);
+        }
+    }
+
+    fn wait_for_footprint(mp: &MemoryPool, footprint: usize) -> bool {
+        let now = Instant::now();
+        while mp.footprint() < footprint {
+            if now.elapsed() > Duration::from_secs(5) {
+                return false;
+            }
+            std::thread::yield_now();
+        }
+        return true;
+    }
+
+    #[test]
+    fn prefaulter() {
+        // The prefaulter thread needs a 'static pool, so leak one.
+        let buffer: &'static mut [AtomicUsize] =
+            Box::leak((0..4096).map(|_| AtomicUsize::new(0)).collect());
+        let buffer_ptr: &'static *mut AtomicUsize = Box::leak(Box::new(buffer.as_mut_ptr()));
+        let mp: &'static MemoryPool = Box::leak(Box::new(unsafe {
+            MemoryPool::from_static_with_policy(64, buffer_ptr, 4096, MemoryPoolPolicy::new(64))
+        }));
+        let prefaulter = Prefaulter::spawn(Duration::from_millis(1), move || {
+            mp.prepare_spare_chunk();
+        });
+        assert!(wait_for_footprint(mp, 64 * 64));
+        for j in 0..4 {
+            // Use up exactly the spare chunk, so the allocator never maps one itself.
+            for _i in 0..64 {
+                assert_ne!(unsafe { mp.allocate() }, core::ptr::null_mut());
+            }
+            assert!(wait_for_footprint(mp, 64 * 64 * (j + 2)));
+        }
+        drop(prefaulter);
+        assert_eq!(mp.footprint(), 64 * 64 * 5);
+        unsafe {
+            mp.clear();
+        }
+    }
+
+    #[test]
+    fn batch() {
+        unsafe {
+            let mut buffer_local: [usize; 1024] = [0; 1024];
+            let buffer_ptr = &mut buffer_local[0] as *mut usize as *mut AtomicUsize;
+            let mp = MemoryPool::from_static(64, &buffer_ptr, 1024);
+
+            let mut storage: [*mut u8; 1024] = [core::ptr::null_mut(); 1024];
+            assert_eq!(mp.allocate_batch(&mut storage[..300]), 300);
+            // Free some, so the next batch is part recycled and part new.
+            mp.deallocate_batch(&storage[..100]);
+            let mut mixed: [*mut u8; 900] = [core::ptr::null_mut(); 900];
+            assert_eq!(mp.allocate_batch(&mut mixed), 824);
+            storage[..100].copy_from_slice(&mixed[..100]);
+            storage[300..].copy_from_slice(&mixed[100..824]);
+            let mut sorted = storage.to_vec();
+            sorted.sort();
+            sorted.dedup();
+            assert_eq!(sorted.len(), 1024);
+            assert_eq!(mp.allocate_batch(&mut storage[..10]), 0);
+
+            assert_eq!(mp.free_count(), 0);
+            mp.deallocate_batch(&storage);
+            assert_eq!(mp.free_count(), 1024);
+            let mut again: [*mut u8; 1024] = [core::ptr::null_mut(); 1024];
+            assert_eq!(mp.allocate_batch(&mut again), 1024);
+            assert_eq!(again, storage);
+            assert_eq!(mp.free_count(), 0);
+            mp.deallocate(again[0]);
+            assert_eq!(mp.free_count(), 1);
+        }
+    }
 }
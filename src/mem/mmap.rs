@@ -1,4 +1,5 @@
 extern crate libc;
+use core::ffi::CStr;
 use core::ptr;
 
 #[derive(Copy, Clone, Debug)]
@@ -52,13 +53,24 @@ pub(crate) unsafe fn free_page_aligned(ptr: *mut u8, size: usize) {
 
 #[inline(always)]
 pub(crate) fn alloc_page_aligned(alloc_size: usize) -> MapAlloc {
+    return alloc_page_aligned_populate(alloc_size, false);
+}
+
+/// Like `alloc_page_aligned`, but optionally asks the kernel to fault every page in up front with `MAP_POPULATE`.
+#[inline(always)]
+pub(crate) fn alloc_page_aligned_populate(alloc_size: usize, populate: bool) -> MapAlloc {
     // let alloc_size = get_page_aligned_size(size);
+    let flags = if populate {
+        libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_POPULATE
+    } else {
+        libc::MAP_PRIVATE | libc::MAP_ANONYMOUS
+    };
     unsafe {
         let p: *mut libc::c_void = libc::mmap(
             core::ptr::null_mut(),
             alloc_size,
             libc::PROT_READ | libc::PROT_WRITE,
-            libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+            flags,
             -1, //no file
             0,
         ); //no offset
@@ -76,5 +88,154 @@ pub(crate) fn alloc_page_aligned(alloc_size: usize) -> MapAlloc {
     }
 }
 
+/// Touch every page in the range, so the page faults happen now rather than on first use.
+#[inline(always)]
+pub(crate) unsafe fn prefault_page_aligned(ptr: *mut u8, size: usize) {
+    let page_size = page_size();
+    let mut offset = 0;
+    while offset < size {
+        // Reading would only map the shared zero page - it has to be a write.
+        ptr::write_volatile(ptr.add(offset), 0);
+        offset += page_size;
+    }
+}
+
+/// Lock the pages in memory, so they are never swapped out.  Fails if it would exceed RLIMIT_MEMLOCK.
+#[inline(always)]
+pub(crate) unsafe fn lock_page_aligned(ptr: *mut u8, size: usize) -> bool {
+    return libc::mlock(ptr as *const libc::c_void, size) == 0;
+}
+
+/// Reserve a range of address space without committing any memory to it.  
+/// Pages in the range are inaccessible until something is mapped over them with `map_file_fixed`.
+#[inline(always)]
+pub(crate) fn reserve_address_range(alloc_size: usize) -> MapAlloc {
+    unsafe {
+        let p: *mut libc::c_void = libc::mmap(
+            core::ptr::null_mut(),
+            alloc_size,
+            libc::PROT_NONE,
+            libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_NORESERVE,
+            -1, //no file
+            0,
+        ); //no offset
+
+        if p == libc::MAP_FAILED {
+            return MapAlloc::null();
+        }
+        return MapAlloc {
+            size: alloc_size,
+            memory: p as *mut u8,
+        };
+    }
+}
+
+/// Map a page aligned region of a file over an address previously reserved with `reserve_address_range`.  
+/// Writes to the mapping are shared, and will be written back to the file.
+#[inline(always)]
+pub(crate) unsafe fn map_file_fixed(
+    fd: libc::c_int,
+    file_offset: usize,
+    address: *mut u8,
+    size: usize,
+) -> bool {
+    let p: *mut libc::c_void = libc::mmap(
+        address as *mut libc::c_void,
+        size,
+        libc::PROT_READ | libc::PROT_WRITE,
+        libc::MAP_SHARED | libc::MAP_FIXED,
+        fd,
+        file_offset as libc::off_t,
+    );
+    return p != libc::MAP_FAILED;
+}
+
+/// Flush a shared mapping back to its file, blocking until the write completes.
+#[inline(always)]
+pub(crate) unsafe fn sync_page_aligned(ptr: *mut u8, size: usize) -> bool {
+    return libc::msync(ptr as *mut libc::c_void, size, libc::MS_SYNC) == 0;
+}
+
+/// Create a new named POSIX shared memory object of the given size.  Fails if the name already exists.
+/// Returns the file descriptor, or -1 on failure.
+pub(crate) fn shared_memory_create(name: &CStr, size: usize) -> libc::c_int {
+    unsafe {
+        let fd = libc::shm_open(
+            name.as_ptr(),
+            libc::O_RDWR | libc::O_CREAT | libc::O_EXCL | libc::O_CLOEXEC,
+            0o600,
+        );
+        if fd < 0 {
+            return -1;
+        }
+        if libc::ftruncate(fd, size as libc::off_t) != 0 {
+            libc::close(fd);
+            libc::shm_unlink(name.as_ptr());
+            return -1;
+        }
+        return fd;
+    }
+}
+
+/// Open an existing named POSIX shared memory object.  Returns the file descriptor, or -1 on failure.
+pub(crate) fn shared_memory_open(name: &CStr) -> libc::c_int {
+    unsafe {
+        return libc::shm_open(name.as_ptr(), libc::O_RDWR | libc::O_CLOEXEC, 0);
+    }
+}
+
+/// Remove a named POSIX shared memory object.  Existing mappings stay valid until unmapped.
+pub(crate) fn shared_memory_unlink(name: &CStr) -> bool {
+    unsafe {
+        return libc::shm_unlink(name.as_ptr()) == 0;
+    }
+}
+
+/// Create an anonymous shared memory file with `memfd_create`.  The name is only used for debugging.
+/// It can be shared with child processes through fork, or by passing the descriptor.  Returns -1 on failure.
+pub(crate) fn shared_memory_anonymous(name: &CStr, size: usize) -> libc::c_int {
+    unsafe {
+        let fd = libc::memfd_create(name.as_ptr(), libc::MFD_CLOEXEC);
+        if fd < 0 {
+            return -1;
+        }
+        if libc::ftruncate(fd, size as libc::off_t) != 0 {
+            libc::close(fd);
+            return -1;
+        }
+        return fd;
+    }
+}
+
+/// The current size of the file behind a descriptor.
+pub(crate) fn file_size(fd: libc::c_int) -> usize {
+    unsafe {
+        let mut stat = core::mem::MaybeUninit::<libc::stat>::uninit();
+        if libc::fstat(fd, stat.as_mut_ptr()) != 0 {
+            return 0;
+        }
+        return stat.assume_init().st_size as usize;
+    }
+}
+
+/// Map a shared file read/write, wherever the kernel chooses.
+pub(crate) unsafe fn map_shared(fd: libc::c_int, size: usize) -> MapAlloc {
+    let p: *mut libc::c_void = libc::mmap(
+        ptr::null_mut(),
+        size,
+        libc::PROT_READ | libc::PROT_WRITE,
+        libc::MAP_SHARED,
+        fd,
+        0,
+    );
+    if p == libc::MAP_FAILED {
+        return MapAlloc::null();
+    }
+    return MapAlloc {
+        size: size,
+        memory: p as *mut u8,
+    };
+}
+
 #[cfg(test)]
 mod test;
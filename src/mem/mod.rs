@@ -3,10 +3,32 @@ mod memory_manager;
 mod memory_pool;
 mod mmap;
 mod nullable;
+mod persistent_pool;
 mod queue;
+mod resource_cache;
 mod resource_manager;
+mod shared_pool;
+pub use queue::AsyncQueue;
+pub use queue::AtomicSlot;
+pub use queue::BroadcastCursor;
+pub use queue::BroadcastQueue;
+pub use queue::BroadcastReceiver;
+pub use queue::Dequeue;
+pub use queue::DequeWorker;
+pub use queue::Drain;
+pub use queue::Enqueue;
+pub use queue::LockFreeQueue;
+pub use queue::Queue;
+pub use queue::QueueElement;
 pub use queue::QueueU32;
 pub use queue::QueueUsize;
+pub use queue::SegmentedQueue;
+pub use queue::SequencedCell;
+pub use queue::SpscConsumer;
+pub use queue::SpscProducer;
+pub use queue::SpscQueue;
+pub use queue::WorkStealingDeque;
+pub use queue::MAX_WAKERS;
 pub use queue::QUEUE_NULL;
 pub use queue::QUEUE_U32_NULL;
 
@@ -19,7 +41,23 @@ pub use indexed_data_store::IndexedRef;
 
 pub use memory_manager::MemoryManager;
 pub use memory_pool::MemoryPool;
+pub use memory_pool::MemoryPoolPolicy;
+pub use memory_pool::PoolScope;
+#[cfg(any(test, feature = "std"))]
+pub use memory_pool::Prefaulter;
+pub use persistent_pool::PersistentMemoryPool;
+pub use shared_pool::SharedMemoryPool;
+pub use resource_cache::hash_key;
+pub use resource_cache::CacheEntry;
+pub use resource_cache::ResourceCache;
+pub use resource_manager::LockedResourceManager;
 pub use resource_manager::ResourceData;
+pub use resource_manager::ResourceGuard;
 pub use resource_manager::ResourceHandle;
+pub use resource_manager::ResourceIter;
 pub use resource_manager::ResourceManager;
+pub use resource_manager::ResourceReadGuard;
 pub use resource_manager::ResourceRef;
+pub use resource_manager::ResourceState;
+pub use resource_manager::ResourceWriteGuard;
+pub use resource_manager::MAX_RESOURCE_CAPACITY;
@@ -0,0 +1,368 @@
+use crate::mem::mmap;
+use crate::sync::IndexSpinlock;
+use core::convert::TryFrom;
+use core::ffi::CStr;
+use core::mem::size_of;
+use core::ptr;
+
+const PERSISTENT_MAGIC: u64 = 0x4c4f_4f50_4f43_4931; // "1ICOPOOL"
+const PERSISTENT_VERSION: u32 = 1;
+
+/// The on-disk header, stored in the first page of the file.
+/// Everything in here is an offset or a count - never a pointer - so it survives being mapped at a different address.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct PersistentHeader {
+    magic: u64,
+    version: u32,
+    block_size: u32,
+    chunk_blocks: u32,
+    max_chunks: u32,
+    chunk_count: u32,
+    remaining_blocks: u32,
+    free_head: u64,
+    root: u64,
+}
+
+/// A fixed block size memory pool whose chunks are mapped from a file with `MAP_SHARED`.
+/// The whole address range the pool can ever grow to is reserved up front, and chunks are mapped into it in order, so a chunk's offset in the file is its offset from the base address.
+/// Freed blocks are kept on an intrusive free list inside the file, so reopening the file recovers exactly the blocks that were allocated when it was last closed.
+/// Pointers are only meaningful for the current mapping - use `offset_of` and `pointer_at` for anything that must outlive it.
+pub struct PersistentMemoryPool {
+    lock: IndexSpinlock,
+    fd: libc::c_int,
+    reserved: mmap::MapAlloc,
+    header_size: usize,
+    chunk_size: usize,
+}
+
+impl PersistentMemoryPool {
+    #[inline(always)]
+    fn header(&self) -> *mut PersistentHeader {
+        return self.reserved.memory as *mut PersistentHeader;
+    }
+
+    /// The page aligned header and chunk sizes, or None if the parameters are unusable.
+    /// Blocks must hold an aligned free list link, so their size is a non-zero multiple of 8.  Every count must fit the header's u32 fields,
+    /// and the whole reservation must be addressable.
+    fn sizes(block_size: usize, chunk_blocks: usize, max_chunks: usize) -> Option<(usize, usize)> {
+        if block_size < size_of::<u64>()
+            || block_size & (size_of::<u64>() - 1) != 0
+            || chunk_blocks == 0
+            || max_chunks == 0
+        {
+            return None;
+        }
+        u32::try_from(block_size).ok()?;
+        u32::try_from(chunk_blocks).ok()?;
+        u32::try_from(max_chunks).ok()?;
+        let header_size = mmap::get_page_aligned_size(size_of::<PersistentHeader>());
+        let chunk_bytes = block_size.checked_mul(chunk_blocks)?;
+        // Leave room for rounding up to a whole page.
+        chunk_bytes.checked_add(mmap::page_size())?;
+        let chunk_size = mmap::get_page_aligned_size(chunk_bytes);
+        let total = chunk_size.checked_mul(max_chunks)?.checked_add(header_size)?;
+        if total > isize::MAX as usize {
+            return None;
+        }
+        return Some((header_size, chunk_size));
+    }
+
+    /// Whether offset is the start of a block in one of the first chunk_count chunks.
+    fn is_block_offset(
+        header: &PersistentHeader,
+        header_size: usize,
+        chunk_size: usize,
+        offset: usize,
+    ) -> bool {
+        if offset < header_size {
+            return false;
+        }
+        let block_size = header.block_size as usize;
+        let chunk = (offset - header_size) / chunk_size;
+        let within = (offset - header_size) % chunk_size;
+        return chunk < header.chunk_count as usize
+            && within < block_size * header.chunk_blocks as usize
+            && (within / block_size) * block_size == within;
+    }
+
+    /// Create (or truncate) the file at `path` and map an empty pool from it.
+    /// Block size must be a multiple of 8, so each block can hold a free list link.
+    /// Returns None if the sizes are invalid, or the file cannot be created or mapped.
+    pub fn create(
+        path: &CStr,
+        block_size: usize,
+        chunk_blocks: usize,
+        max_chunks: usize,
+    ) -> Option<PersistentMemoryPool> {
+        let (header_size, chunk_size) =
+            PersistentMemoryPool::sizes(block_size, chunk_blocks, max_chunks)?;
+        unsafe {
+            let fd = libc::open(
+                path.as_ptr(),
+                libc::O_RDWR | libc::O_CREAT | libc::O_TRUNC | libc::O_CLOEXEC,
+                0o644,
+            );
+            if fd < 0 {
+                return None;
+            }
+            if libc::ftruncate(fd, header_size as libc::off_t) != 0 {
+                libc::close(fd);
+                return None;
+            }
+            let pool = PersistentMemoryPool::map(fd, header_size, chunk_size, max_chunks, 0)?;
+            ptr::write(
+                pool.header(),
+                PersistentHeader {
+                    magic: PERSISTENT_MAGIC,
+                    version: PERSISTENT_VERSION,
+                    // All checked to fit by sizes.
+                    block_size: block_size as u32,
+                    chunk_blocks: chunk_blocks as u32,
+                    max_chunks: max_chunks as u32,
+                    chunk_count: 0,
+                    remaining_blocks: 0,
+                    free_head: 0,
+                    root: 0,
+                },
+            );
+            return Some(pool);
+        }
+    }
+
+    /// Reopen a pool previously created with `create`.  All blocks that were allocated when the file was last written are still allocated.
+    /// Returns None if the file does not exist, or does not contain a valid pool - every header field is checked against the file before anything is mapped.
+    pub fn open(path: &CStr) -> Option<PersistentMemoryPool> {
+        unsafe {
+            let fd = libc::open(path.as_ptr(), libc::O_RDWR | libc::O_CLOEXEC);
+            if fd < 0 {
+                return None;
+            }
+            let mut header = core::mem::MaybeUninit::<PersistentHeader>::uninit();
+            let read = libc::pread(
+                fd,
+                header.as_mut_ptr() as *mut libc::c_void,
+                size_of::<PersistentHeader>(),
+                0,
+            );
+            if read != size_of::<PersistentHeader>() as isize {
+                libc::close(fd);
+                return None;
+            }
+            let header = header.assume_init();
+            let sizes = match PersistentMemoryPool::validate(fd, &header) {
+                Some(x) => x,
+                None => {
+                    libc::close(fd);
+                    return None;
+                }
+            };
+            let (header_size, chunk_size) = sizes;
+            return PersistentMemoryPool::map(
+                fd,
+                header_size,
+                chunk_size,
+                header.max_chunks as usize,
+                header.chunk_count as usize,
+            );
+        }
+    }
+
+    /// Check a header read from disk, returning the sizes to map it with.
+    /// Mapping chunks the file does not actually contain would fault on first touch, so the file length is checked too.
+    unsafe fn validate(fd: libc::c_int, header: &PersistentHeader) -> Option<(usize, usize)> {
+        if header.magic != PERSISTENT_MAGIC || header.version != PERSISTENT_VERSION {
+            return None;
+        }
+        let (header_size, chunk_size) = PersistentMemoryPool::sizes(
+            header.block_size as usize,
+            header.chunk_blocks as usize,
+            header.max_chunks as usize,
+        )?;
+        if header.chunk_count > header.max_chunks
+            || header.remaining_blocks > header.chunk_blocks
+            || (header.chunk_count == 0 && header.remaining_blocks != 0)
+        {
+            return None;
+        }
+        let mut stat = core::mem::MaybeUninit::<libc::stat>::uninit();
+        if libc::fstat(fd, stat.as_mut_ptr()) != 0 {
+            return None;
+        }
+        // Cannot overflow - it is no bigger than the reservation sizes accepted.
+        let used = header_size + chunk_size * header.chunk_count as usize;
+        if (stat.assume_init().st_size as u64) < used as u64 {
+            return None;
+        }
+        if header.free_head != 0
+            && !PersistentMemoryPool::is_block_offset(
+                header,
+                header_size,
+                chunk_size,
+                header.free_head as usize,
+            )
+        {
+            return None;
+        }
+        return Some((header_size, chunk_size));
+    }
+
+    /// Reserve the full address range, and map the header plus any existing chunks into it.  Takes ownership of the file descriptor.
+    unsafe fn map(
+        fd: libc::c_int,
+        header_size: usize,
+        chunk_size: usize,
+        max_chunks: usize,
+        chunk_count: usize,
+    ) -> Option<PersistentMemoryPool> {
+        let reserved = mmap::reserve_address_range(header_size + chunk_size * max_chunks);
+        if reserved.is_null() {
+            libc::close(fd);
+            return None;
+        }
+        let pool = PersistentMemoryPool {
+            lock: IndexSpinlock::new(0),
+            fd: fd,
+            reserved: reserved,
+            header_size: header_size,
+            chunk_size: chunk_size,
+        };
+        // Dropping the pool releases the reservation and the descriptor on failure.
+        if !mmap::map_file_fixed(
+            fd,
+            0,
+            reserved.memory,
+            header_size + chunk_size * chunk_count,
+        ) {
+            return None;
+        }
+        return Some(pool);
+    }
+
+    /// Extend the file by one chunk and map it at the end of the used range.  Must be called with the lock held.
+    unsafe fn grow(&self, header: &mut PersistentHeader) -> bool {
+        if header.chunk_count >= header.max_chunks {
+            return false;
+        }
+        let offset = self.header_size + self.chunk_size * header.chunk_count as usize;
+        if libc::ftruncate(self.fd, (offset + self.chunk_size) as libc::off_t) != 0 {
+            return false;
+        }
+        if !mmap::map_file_fixed(
+            self.fd,
+            offset,
+            self.reserved.get_unchecked(offset as isize),
+            self.chunk_size,
+        ) {
+            return false;
+        }
+        header.chunk_count += 1;
+        header.remaining_blocks = header.chunk_blocks;
+        return true;
+    }
+
+    /// Allocate a block, reusing freed blocks first.  Returns null if the pool has reached its maximum size, or the file cannot grow.
+    pub unsafe fn allocate(&self) -> *mut u8 {
+        let _lock = self.lock.lock();
+        let header = self.header().as_mut().unwrap();
+
+        if header.free_head != 0 {
+            let block = self.pointer_at(header.free_head as usize);
+            header.free_head = ptr::read(block as *const u64);
+            debug_assert!(
+                header.free_head == 0
+                    || PersistentMemoryPool::is_block_offset(
+                        header,
+                        self.header_size,
+                        self.chunk_size,
+                        header.free_head as usize
+                    )
+            );
+            return block;
+        }
+
+        if header.remaining_blocks == 0 && !self.grow(header) {
+            return ptr::null_mut();
+        }
+        let used_blocks = (header.chunk_blocks - header.remaining_blocks) as usize;
+        let offset = self.header_size
+            + self.chunk_size * (header.chunk_count - 1) as usize
+            + used_blocks * header.block_size as usize;
+        header.remaining_blocks -= 1;
+        return self.pointer_at(offset);
+    }
+
+    /// Return a block to the pool.  The free list lives in the file, so this is remembered across runs.
+    /// This is unsafe, because if you pass back a bad pointer there is no checking.
+    pub unsafe fn deallocate(&self, ptr: *mut u8) {
+        let offset = self.offset_of(ptr);
+        let _lock = self.lock.lock();
+        let header = self.header().as_mut().unwrap();
+        ptr::write(ptr as *mut u64, header.free_head);
+        header.free_head = offset as u64;
+    }
+
+    /// Convert a pointer into this pool into an offset that stays valid across runs.  Offsets are never zero.
+    #[inline(always)]
+    pub fn offset_of(&self, ptr: *const u8) -> usize {
+        debug_assert!(ptr as usize >= self.reserved.memory as usize + self.header_size);
+        debug_assert!((ptr as usize) < self.reserved.memory as usize + self.reserved.size);
+        return ptr as usize - self.reserved.memory as usize;
+    }
+
+    /// Convert an offset previously returned by `offset_of` back into a pointer for the current mapping.
+    #[inline(always)]
+    pub unsafe fn pointer_at(&self, offset: usize) -> *mut u8 {
+        return self.reserved.get_unchecked(offset as isize);
+    }
+
+    /// The offset of the user's root object, as last stored with `set_root`.  Zero if none has been stored.
+    pub fn root(&self) -> usize {
+        let _lock = self.lock.lock();
+        return unsafe { (*self.header()).root as usize };
+    }
+
+    /// Record the offset of a root object, so it can be found again after reopening the file.
+    pub fn set_root(&self, offset: usize) {
+        let _lock = self.lock.lock();
+        unsafe {
+            (*self.header()).root = offset as u64;
+        }
+    }
+
+    /// Size of each block in bytes.
+    pub fn block_size(&self) -> usize {
+        return unsafe { (*self.header()).block_size as usize };
+    }
+
+    /// How many chunks are currently mapped from the file.
+    pub fn chunk_count(&self) -> usize {
+        let _lock = self.lock.lock();
+        return unsafe { (*self.header()).chunk_count as usize };
+    }
+
+    /// Write all dirty pages back to the file, blocking until complete.
+    pub fn flush(&self) -> bool {
+        let _lock = self.lock.lock();
+        unsafe {
+            let used = self.header_size + self.chunk_size * (*self.header()).chunk_count as usize;
+            return mmap::sync_page_aligned(self.reserved.memory, used);
+        }
+    }
+}
+
+unsafe impl Send for PersistentMemoryPool {}
+unsafe impl Sync for PersistentMemoryPool {}
+
+impl Drop for PersistentMemoryPool {
+    fn drop(&mut self) {
+        unsafe {
+            // Shared mappings are written back by the kernel - unmapping does not discard anything.
+            mmap::free_page_aligned(self.reserved.memory, self.reserved.size);
+            libc::close(self.fd);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,153 @@
+use crate::mem::PersistentMemoryPool;
+use std::ffi::CString;
+
+fn temp_path(name: &str) -> CString {
+    let mut path = std::env::temp_dir();
+    path.push(format!("ico_memory_{}_{}", std::process::id(), name));
+    return CString::new(path.to_str().unwrap()).unwrap();
+}
+
+#[test]
+fn alloc_free() {
+    let path = temp_path("alloc_free");
+    unsafe {
+        let pool = PersistentMemoryPool::create(&path, 64, 64, 16).unwrap();
+        let mut storage: [*mut u8; 1024] = [core::ptr::null_mut(); 1024];
+        for block in storage.iter_mut() {
+            *block = pool.allocate();
+            assert_ne!(*block, core::ptr::null_mut());
+        }
+        assert_eq!(pool.allocate(), core::ptr::null_mut());
+        assert_eq!(pool.chunk_count(), 16);
+
+        for block in storage.iter() {
+            pool.deallocate(*block);
+        }
+        for _i in 0..1024 {
+            assert_ne!(pool.allocate(), core::ptr::null_mut());
+        }
+        assert_eq!(pool.chunk_count(), 16);
+        libc::unlink(path.as_ptr());
+    }
+}
+
+#[test]
+fn reopen() {
+    let path = temp_path("reopen");
+    let mut offsets = Vec::new();
+    unsafe {
+        let pool = PersistentMemoryPool::create(&path, 64, 16, 64).unwrap();
+        for i in 0..100u64 {
+            let block = pool.allocate();
+            *(block as *mut u64) = i;
+            offsets.push(pool.offset_of(block));
+        }
+        // Free every other block, so the free list must be recovered too.
+        for i in (0..100).step_by(2) {
+            pool.deallocate(pool.pointer_at(offsets[i]));
+        }
+        pool.set_root(offsets[1]);
+        assert!(pool.flush());
+    }
+
+    unsafe {
+        let pool = PersistentMemoryPool::open(&path).unwrap();
+        assert_eq!(pool.block_size(), 64);
+        assert_eq!(pool.root(), offsets[1]);
+        for i in (1..100).step_by(2) {
+            assert_eq!(*(pool.pointer_at(offsets[i]) as *const u64), i as u64);
+        }
+
+        // The freed blocks are handed out again before the pool grows.
+        let chunks = pool.chunk_count();
+        let mut reused = Vec::new();
+        for _i in 0..50 {
+            reused.push(pool.offset_of(pool.allocate()));
+        }
+        assert_eq!(pool.chunk_count(), chunks);
+        reused.sort();
+        let freed: Vec<usize> = (0..100).step_by(2).map(|i| offsets[i]).collect();
+        assert_eq!(reused, freed);
+        libc::unlink(path.as_ptr());
+    }
+}
+
+#[test]
+fn open_invalid() {
+    let path = temp_path("open_invalid");
+    assert!(PersistentMemoryPool::open(&path).is_none());
+    unsafe {
+        let fd = libc::open(path.as_ptr(), libc::O_RDWR | libc::O_CREAT, 0o644);
+        libc::ftruncate(fd, 4096);
+        libc::close(fd);
+    }
+    assert!(PersistentMemoryPool::open(&path).is_none());
+    unsafe {
+        libc::unlink(path.as_ptr());
+    }
+}
+
+/// Overwrite one u32 header field in a closed pool file.
+fn patch_header(path: &CString, offset: i64, value: u32) {
+    unsafe {
+        let fd = libc::open(path.as_ptr(), libc::O_RDWR);
+        assert_eq!(
+            libc::pwrite(fd, &value as *const u32 as *const libc::c_void, 4, offset),
+            4
+        );
+        libc::close(fd);
+    }
+}
+
+#[test]
+fn create_invalid() {
+    let path = temp_path("create_invalid");
+    // Blocks must hold an aligned u64 link.
+    assert!(PersistentMemoryPool::create(&path, 4, 16, 4).is_none());
+    assert!(PersistentMemoryPool::create(&path, 12, 16, 4).is_none());
+    assert!(PersistentMemoryPool::create(&path, 64, 0, 4).is_none());
+    // Sizes that do not fit the header, or the address space.
+    assert!(PersistentMemoryPool::create(&path, 1 << 32, 1, 1).is_none());
+    assert!(PersistentMemoryPool::create(&path, 1 << 31, 1 << 31, 1 << 31).is_none());
+    assert!(PersistentMemoryPool::create(&path, 24, 16, 4).is_some());
+    unsafe {
+        libc::unlink(path.as_ptr());
+    }
+}
+
+#[test]
+fn open_corrupt() {
+    let path = temp_path("open_corrupt");
+    let make = || unsafe {
+        let pool = PersistentMemoryPool::create(&path, 64, 16, 4).unwrap();
+        let block = pool.allocate();
+        pool.deallocate(block);
+        assert!(pool.flush());
+    };
+    // Header layout: block_size 12, chunk_blocks 16, max_chunks 20, chunk_count 24, remaining_blocks 28, free_head 32.
+    let cases: [(i64, u32); 8] = [
+        (12, 4),
+        (12, 60),
+        (16, 0),
+        (12, 0x8000_0000),
+        (20, 0),
+        (24, 3),
+        (28, 17),
+        (32, 4096 + 8),
+    ];
+    for (offset, value) in cases.iter() {
+        make();
+        assert!(PersistentMemoryPool::open(&path).is_some());
+        patch_header(&path, *offset, *value);
+        assert!(PersistentMemoryPool::open(&path).is_none());
+    }
+    // A file cut short of the chunks its header claims.
+    make();
+    unsafe {
+        libc::truncate(path.as_ptr(), 4096);
+    }
+    assert!(PersistentMemoryPool::open(&path).is_none());
+    unsafe {
+        libc::unlink(path.as_ptr());
+    }
+}
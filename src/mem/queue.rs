@@ -1,9 +1,20 @@
+#[cfg(all(any(test, feature = "std"), target_os = "linux"))]
+use crate::sync::futex_wait;
+#[cfg(all(any(test, feature = "std"), target_os = "linux"))]
+use crate::sync::futex_wake;
 use crate::sync::IndexSpinlock;
 use core::marker::PhantomData;
+use core::num::NonZeroU32;
+use core::num::NonZeroU64;
 use core::num::NonZeroUsize;
 use core::sync::atomic::AtomicU32;
+use core::sync::atomic::AtomicU64;
 use core::sync::atomic::AtomicUsize;
 use core::sync::atomic::Ordering;
+#[cfg(all(any(test, feature = "std"), target_os = "linux"))]
+use core::time::Duration;
+#[cfg(all(any(test, feature = "std"), target_os = "linux"))]
+use std::time::Instant;
 
 // #[allow(dead_code)]
 // #[allow(unions_with_drop_fields)]
@@ -47,14 +58,158 @@ impl<T> Unique<T> {
     }
 }
 
+/// An atomic integer a Queue can keep its elements in.
+pub trait AtomicSlot {
+    type Value: Copy + PartialEq;
+    fn new(value: Self::Value) -> Self;
+    fn load(&self, order: Ordering) -> Self::Value;
+    fn store(&self, value: Self::Value, order: Ordering);
+}
+
+impl AtomicSlot for AtomicU32 {
+    type Value = u32;
+    #[inline(always)]
+    fn new(value: u32) -> AtomicU32 {
+        return AtomicU32::new(value);
+    }
+    #[inline(always)]
+    fn load(&self, order: Ordering) -> u32 {
+        return AtomicU32::load(self, order);
+    }
+    #[inline(always)]
+    fn store(&self, value: u32, order: Ordering) {
+        AtomicU32::store(self, value, order);
+    }
+}
+
+impl AtomicSlot for AtomicUsize {
+    type Value = usize;
+    #[inline(always)]
+    fn new(value: usize) -> AtomicUsize {
+        return AtomicUsize::new(value);
+    }
+    #[inline(always)]
+    fn load(&self, order: Ordering) -> usize {
+        return AtomicUsize::load(self, order);
+    }
+    #[inline(always)]
+    fn store(&self, value: usize, order: Ordering) {
+        AtomicUsize::store(self, value, order);
+    }
+}
+
+impl AtomicSlot for AtomicU64 {
+    type Value = u64;
+    #[inline(always)]
+    fn new(value: u64) -> AtomicU64 {
+        return AtomicU64::new(value);
+    }
+    #[inline(always)]
+    fn load(&self, order: Ordering) -> u64 {
+        return AtomicU64::load(self, order);
+    }
+    #[inline(always)]
+    fn store(&self, value: u64, order: Ordering) {
+        AtomicU64::store(self, value, order);
+    }
+}
+
+/// A value that can be stored in a Queue.  One bit pattern of the underlying atomic, NULL, marks an empty slot - a value that converts to it is refused by enqueue.
+///
+/// # Safety
+/// from_raw is trusted to rebuild a valid element from anything into_raw produced, other than NULL.
+pub unsafe trait QueueElement: Copy {
+    type Slot: AtomicSlot;
+    const NULL: <Self::Slot as AtomicSlot>::Value;
+    fn into_raw(self) -> <Self::Slot as AtomicSlot>::Value;
+    unsafe fn from_raw(raw: <Self::Slot as AtomicSlot>::Value) -> Self;
+}
+
+unsafe impl QueueElement for u32 {
+    type Slot = AtomicU32;
+    const NULL: u32 = QUEUE_U32_NULL;
+    #[inline(always)]
+    fn into_raw(self) -> u32 {
+        return self;
+    }
+    #[inline(always)]
+    unsafe fn from_raw(raw: u32) -> u32 {
+        return raw;
+    }
+}
+
+unsafe impl QueueElement for usize {
+    type Slot = AtomicUsize;
+    const NULL: usize = usize::MAX;
+    #[inline(always)]
+    fn into_raw(self) -> usize {
+        return self;
+    }
+    #[inline(always)]
+    unsafe fn from_raw(raw: usize) -> usize {
+        return raw;
+    }
+}
+
+unsafe impl QueueElement for u64 {
+    type Slot = AtomicU64;
+    const NULL: u64 = u64::MAX;
+    #[inline(always)]
+    fn into_raw(self) -> u64 {
+        return self;
+    }
+    #[inline(always)]
+    unsafe fn from_raw(raw: u64) -> u64 {
+        return raw;
+    }
+}
+
+unsafe impl QueueElement for NonZeroU32 {
+    type Slot = AtomicU32;
+    const NULL: u32 = 0;
+    #[inline(always)]
+    fn into_raw(self) -> u32 {
+        return self.get();
+    }
+    #[inline(always)]
+    unsafe fn from_raw(raw: u32) -> NonZeroU32 {
+        return NonZeroU32::new_unchecked(raw);
+    }
+}
+
+unsafe impl QueueElement for NonZeroUsize {
+    type Slot = AtomicUsize;
+    const NULL: usize = QUEUE_NULL;
+    #[inline(always)]
+    fn into_raw(self) -> usize {
+        return self.get();
+    }
+    #[inline(always)]
+    unsafe fn from_raw(raw: usize) -> NonZeroUsize {
+        return NonZeroUsize::new_unchecked(raw);
+    }
+}
+
+unsafe impl QueueElement for NonZeroU64 {
+    type Slot = AtomicU64;
+    const NULL: u64 = 0;
+    #[inline(always)]
+    fn into_raw(self) -> u64 {
+        return self.get();
+    }
+    #[inline(always)]
+    unsafe fn from_raw(raw: u64) -> NonZeroU64 {
+        return NonZeroU64::new_unchecked(raw);
+    }
+}
+
 /// A MPMC Queue based on Dmitry Vyukov's queue.  
 /// However, there is a slight modification where head and tail can be locked, as my implementation of Dmitry's queue failed some tests under peak contention  - and I've opted for a more conservative queue
-pub const QUEUE_NULL: usize = 0;
+/// The buffer must be filled with the element type's NULL before use.
 #[repr(C)]
-pub struct QueueUsize<'a> {
+pub struct Queue<'a, T: QueueElement> {
     _cache_pad_0: [u8; 64],
-    // buffer: &'a [AtomicUsize],
-    buffer: Unique<AtomicUsize>,
+    buffer: Unique<T::Slot>,
     // buffer_ptr : *const AtomicUsize,
     capacity: u32,
     buffer_capacity_mask: u32,
@@ -63,215 +218,393 @@ pub struct QueueUsize<'a> {
     _cache_pad_2: [u8; 64],
     tail: IndexSpinlock,
     _cache_pad_3: [u8; 64],
-    _lifetime: PhantomData<&'a AtomicUsize>,
+    // Bumped to wake threads blocked in dequeue_wait / enqueue_wait.  Producers and consumers only touch these when the waiter counts are non-zero.
+    not_empty: AtomicU32,
+    not_empty_waiters: AtomicU32,
+    not_full: AtomicU32,
+    not_full_waiters: AtomicU32,
+    // Set by with_capacity, in which case the buffer is freed on drop.
+    owned_buffer: bool,
+    // Non-zero for queues built by initialize_relative, whose buffer is found this many bytes past the queue instead of through `buffer`.
+    buffer_offset: usize,
+    _lifetime: PhantomData<&'a T::Slot>,
+    _element: PhantomData<T>,
 }
 
-impl<'a> QueueUsize<'a> {
-    // const CAPACITY_MASK : u32 = CAPACITY as u32 - 1;
+pub const QUEUE_NULL: usize = 0;
+pub const QUEUE_U32_NULL: u32 = 0xFFFFFFFF;
+
+/// A queue of non-zero usize values, such as pointers.  The buffer starts out zeroed.
+pub type QueueUsize<'a> = Queue<'a, NonZeroUsize>;
+/// A queue of u32 values.  0xFFFFFFFF is reserved, and the buffer must be filled with QUEUE_U32_NULL.
+pub type QueueU32<'a> = Queue<'a, u32>;
 
-    pub const unsafe fn from_static(
-        slice: &'a *mut AtomicUsize,
-        capacity: usize,
-    ) -> QueueUsize<'a> {
+impl<'a, T: QueueElement> Queue<'a, T> {
+    /// This method is a kludge to work around lack of stable const-generics, const unions, etc.  
+    /// It is up to the caller to ensure that the pointer passed in is truly static, and is not mutated externally.
+    /// Capacity must be a non-zero power of two, no larger than 2^31 - in a static, getting this wrong fails to compile.
+    pub const unsafe fn from_static(slice: &'a *mut T::Slot, capacity: usize) -> Queue<'a, T> {
         //pub const fn new(buffer_ptr : *const usize, capacity : usize)->Queue{
+        return Queue::from_raw(*slice, capacity);
+    }
 
-        return QueueUsize {
+    const unsafe fn from_raw(buffer: *mut T::Slot, capacity: usize) -> Queue<'a, T> {
+        assert!(capacity.is_power_of_two() && capacity <= 1 << 31);
+        return Queue {
             head: IndexSpinlock::new(0),
             tail: IndexSpinlock::new(0),
-            buffer: Unique::new(*slice),
-            // buffer_ptr : slice.as_ptr() as *const AtomicUsize,
+            buffer: Unique::new(buffer),
             capacity: capacity as u32,
+            // buffer_ptr : slice.as_ptr() as *const AtomicUsize,
             buffer_capacity_mask: capacity as u32 - 1,
             _cache_pad_0: [0; 64],
             _cache_pad_1: [0; 64],
             _cache_pad_2: [0; 64],
             _cache_pad_3: [0; 64],
+            not_empty: AtomicU32::new(0),
+            not_empty_waiters: AtomicU32::new(0),
+            not_full: AtomicU32::new(0),
+            not_full_waiters: AtomicU32::new(0),
+            owned_buffer: false,
+            buffer_offset: 0,
             _lifetime: PhantomData,
+            _element: PhantomData,
         };
     }
+
+    /// Build a queue in place, over a buffer found by its offset from the queue rather than by its address.
+    /// The queue then holds no addresses, so it keeps working in a region that several processes map at different addresses - SharedMemoryPool keeps its free list in one.
+    /// The buffer must follow the queue in the same mapping, be filled with the element type's NULL, and outlive the queue.  Capacity is checked as in from_static.
+    pub unsafe fn initialize_relative(
+        queue: *mut Queue<'a, T>,
+        buffer: *mut T::Slot,
+        capacity: usize,
+    ) {
+        assert!(buffer as usize > queue as usize);
+        let mut value = Queue::from_raw(core::ptr::null_mut(), capacity);
+        value.buffer_offset = buffer as usize - queue as usize;
+        core::ptr::write(queue, value);
+    }
+
+    /// Build a queue over a caller provided buffer, checking what from_static leaves to the caller.
+    /// Returns None unless the length is a power of two no larger than 2^31, and every slot holds the element type's NULL.
+    pub fn from_slice(slice: &'a [T::Slot]) -> Option<Queue<'a, T>> {
+        let capacity = slice.len();
+        if !capacity.is_power_of_two() || capacity > 1 << 31 {
+            return None;
+        }
+        if slice.iter().any(|x| x.load(Ordering::Relaxed) != T::NULL) {
+            return None;
+        }
+        return Some(unsafe { Queue::from_raw(slice.as_ptr() as *mut T::Slot, capacity) });
+    }
+
+    #[inline(always)]
+    fn get_storage(&self, index: u32) -> &T::Slot {
+        let buffer = if self.buffer_offset != 0 {
+            unsafe { (self as *const Self as *mut u8).add(self.buffer_offset) as *mut T::Slot }
+        } else {
+            self.buffer.as_ptr()
+        };
+        return unsafe { buffer.offset(index as isize).as_ref().unwrap() };
+    }
+
     pub fn clear(&self) {
         let mut tail = self.tail.lock();
         let mut head = self.head.lock();
         for i in 0..self.capacity {
-            unsafe {
-                self.buffer
-                    .as_ptr()
-                    .offset(i as isize)
-                    .as_ref()
-                    .unwrap()
-                    .store(QUEUE_NULL, Ordering::Relaxed);
-            }
+            self.get_storage(i).store(T::NULL, Ordering::Relaxed);
         }
         tail.write(0);
         head.write(0);
+        let wake = self.not_full_waiters.load(Ordering::Relaxed);
+        drop(head);
+        drop(tail);
+        Queue::<T>::wake(&self.not_full, wake);
     }
 
-    pub fn enqueue(&self, value: NonZeroUsize) -> bool {
-        let v = value.get();
-        debug_assert_ne!(v, QUEUE_NULL);
+    /// Wake up to `count` threads blocked on `sequence`.  A count of zero means nobody was waiting, which is the common case.
+    #[inline(always)]
+    fn wake(sequence: &AtomicU32, count: u32) {
+        if count == 0 {
+            return;
+        }
+        sequence.fetch_add(1, Ordering::Release);
+        #[cfg(all(any(test, feature = "std"), target_os = "linux"))]
+        futex_wake(sequence, core::cmp::min(count, i32::MAX as u32) as i32);
+    }
+
+    /// Returns false if the queue is full, or the value converts to the element type's NULL and so cannot be told apart from an empty slot.
+    pub fn enqueue(&self, value: T) -> bool {
+        let v = value.into_raw();
+        if v == T::NULL {
+            return false;
+        }
 
         let mut tail = self.tail.lock();
         let tail_value = tail.read();
 
-        let storage = unsafe {
-            self.buffer
-                .as_ptr()
-                .offset(tail_value as isize)
-                .as_ref()
-                .unwrap()
-        }; //self.get_storage(tail_value as usize);
+        let storage = self.get_storage(tail_value);
         let stored_value = storage.load(Ordering::Relaxed);
-        if stored_value != QUEUE_NULL {
+        if stored_value != T::NULL {
             return false;
         }
         storage.store(v, Ordering::Relaxed);
         tail.write(tail_value.wrapping_add(1) & self.buffer_capacity_mask);
+        // Waiters register before passing through the tail lock, so reading the count while we hold it cannot miss one.
+        let waiters = self.not_empty_waiters.load(Ordering::Relaxed);
+        drop(tail);
+        Queue::<T>::wake(&self.not_empty, core::cmp::min(waiters, 1));
         return true;
     }
 
-    pub fn dequeue(&self) -> Option<NonZeroUsize> {
+    pub fn dequeue(&self) -> Option<T> {
         let mut head = self.head.lock();
         let head_value = head.read();
-        let storage = unsafe {
-            self.buffer
-                .as_ptr()
-                .offset(head_value as isize)
-                .as_ref()
-                .unwrap()
-        }; //self.get_storage(head_value as usize);
+        let storage = self.get_storage(head_value);
         let stored_value = storage.load(Ordering::Relaxed);
-        if stored_value == QUEUE_NULL {
+        if stored_value == T::NULL {
             return None;
         }
-        storage.store(QUEUE_NULL, Ordering::Relaxed);
+        storage.store(T::NULL, Ordering::Relaxed);
         head.write(head_value.wrapping_add(1) & self.buffer_capacity_mask);
+        let waiters = self.not_full_waiters.load(Ordering::Relaxed);
+        drop(head);
+        Queue::<T>::wake(&self.not_full, core::cmp::min(waiters, 1));
         unsafe {
-            return Some(NonZeroUsize::new_unchecked(stored_value));
+            return Some(T::from_raw(stored_value));
         }
     }
-}
 
-unsafe impl<'a> Send for QueueUsize<'a> {}
-unsafe impl<'a> Sync for QueueUsize<'a> {}
-
-#[repr(C)]
-pub struct QueueU32<'a> {
-    _cache_pad_0: [u8; 64],
-    buffer: Unique<AtomicU32>,
-    // buffer_ptr : *const AtomicUsize,
-    capacity: u32,
-    // buffer_ptr : *const AtomicUsize,
-    buffer_capacity_mask: u32,
-    _cache_pad_1: [u8; 64],
-    head: IndexSpinlock,
-    _cache_pad_2: [u8; 64],
-    tail: IndexSpinlock,
-    _cache_pad_3: [u8; 64],
-    _lifetime: PhantomData<&'a AtomicUsize>,
-}
+    /// Enqueue as many of `values` as fit, in order, taking the tail lock once.  Returns how many were enqueued.
+    /// If any value converts to the element type's NULL, none are enqueued and this returns 0.
+    pub fn enqueue_batch(&self, values: &[T]) -> usize {
+        if values.iter().any(|x| x.into_raw() == T::NULL) {
+            return 0;
+        }
+        let mut tail = self.tail.lock();
+        let mut tail_value = tail.read();
+        let mut count = 0;
+        for value in values {
+            let v = value.into_raw();
+            let storage = self.get_storage(tail_value);
+            if storage.load(Ordering::Relaxed) != T::NULL {
+                break;
+            }
+            storage.store(v, Ordering::Relaxed);
+            tail_value = tail_value.wrapping_add(1) & self.buffer_capacity_mask;
+            count += 1;
+        }
+        tail.write(tail_value);
+        let waiters = self.not_empty_waiters.load(Ordering::Relaxed);
+        drop(tail);
+        Queue::<T>::wake(&self.not_empty, core::cmp::min(waiters, count as u32));
+        return count;
+    }
 
-pub const QUEUE_U32_NULL: u32 = 0xFFFFFFFF;
-impl<'a> QueueU32<'a> {
-    // const CAPACITY_MASK : u32 = CAPACITY as u32 - 1;
-
-    // #[cfg(any(test, feature = "std"))]
-    // pub fn new(capacity: usize) -> QueueU32 {
-    //     return QueueU32 {
-    //         head: IndexSpinlock::new(0),
-    //         tail: IndexSpinlock::new(0),
-    //         buffer: Unique::new(slice),
-    //         capacity: capacity as u32,
-    //         // buffer_ptr : slice.as_ptr() as *const AtomicUsize,
-    //         buffer_capacity_mask: capacity as u32 - 1,
-    //         _cache_pad_0: [0; 64],
-    //         _cache_pad_1: [0; 64],
-    //         _cache_pad_2: [0; 64],
-    //         _cache_pad_3: [0; 64],
-    //     };
-
-    // }
-    /// This method is a kludge to work around lack of stable const-generics, const unions, etc.  
-    /// It is up to the caller to ensure that the pointer passed in is truly static, and is not mutated externally.
-    /// Capacity must be a non-zero power of two.
-    pub const unsafe fn from_static(slice: &'a *mut AtomicU32, capacity: usize) -> QueueU32<'a> {
-        //pub const fn new(buffer_ptr : *const usize, capacity : usize)->Queue{
+    /// Dequeue into `values` until it is full or the queue is empty, taking the head lock once.  Returns how many were dequeued.
+    pub fn dequeue_batch(&self, values: &mut [T]) -> usize {
+        let mut head = self.head.lock();
+        let mut head_value = head.read();
+        let mut count = 0;
+        for value in values.iter_mut() {
+            let storage = self.get_storage(head_value);
+            let stored_value = storage.load(Ordering::Relaxed);
+            if stored_value == T::NULL {
+                break;
+            }
+            storage.store(T::NULL, Ordering::Relaxed);
+            *value = unsafe { T::from_raw(stored_value) };
+            head_value = head_value.wrapping_add(1) & self.buffer_capacity_mask;
+            count += 1;
+        }
+        head.write(head_value);
+        let waiters = self.not_full_waiters.load(Ordering::Relaxed);
+        drop(head);
+        Queue::<T>::wake(&self.not_full, core::cmp::min(waiters, count as u32));
+        return count;
+    }
 
-        return QueueU32 {
-            head: IndexSpinlock::new(0),
-            tail: IndexSpinlock::new(0),
-            buffer: Unique::new(*slice),
-            capacity: capacity as u32,
-            // buffer_ptr : slice.as_ptr() as *const AtomicUsize,
-            buffer_capacity_mask: capacity as u32 - 1,
-            _cache_pad_0: [0; 64],
-            _cache_pad_1: [0; 64],
-            _cache_pad_2: [0; 64],
-            _cache_pad_3: [0; 64],
-            _lifetime: PhantomData,
-        };
+    /// Total number of elements the queue can hold.
+    #[inline(always)]
+    pub fn capacity(&self) -> usize {
+        return self.capacity as usize;
     }
-    pub fn clear(&self) {
-        let mut tail = self.tail.lock();
-        let mut head = self.head.lock();
-        for i in 0..self.capacity {
-            unsafe {
-                self.buffer
-                    .as_ptr()
-                    .offset(i as isize)
-                    .as_ref()
-                    .unwrap()
-                    .store(QUEUE_U32_NULL, Ordering::Relaxed);
+
+    /// Number of elements in the queue.  This does not lock, so with concurrent producers and consumers it is only a snapshot - but always between 0 and capacity.
+    pub fn len(&self) -> usize {
+        let head = self.head.peek();
+        let tail = self.tail.peek();
+        let len = tail.wrapping_sub(head) & self.buffer_capacity_mask;
+        if len == 0 {
+            // Head and tail meet both when empty and when full - the slot at head tells them apart.
+            if self.get_storage(head).load(Ordering::Relaxed) != T::NULL {
+                return self.capacity as usize;
             }
         }
-        tail.write(0);
-        head.write(0);
+        return len as usize;
+    }
+
+    /// A snapshot, like len.
+    pub fn is_empty(&self) -> bool {
+        return self.get_storage(self.head.peek()).load(Ordering::Relaxed) == T::NULL;
     }
 
-    pub fn enqueue(&self, value: u32) -> bool {
-        debug_assert_ne!(value, QUEUE_U32_NULL);
+    /// A snapshot, like len.
+    pub fn is_full(&self) -> bool {
+        return self.get_storage(self.tail.peek()).load(Ordering::Relaxed) != T::NULL;
+    }
 
-        let mut tail = self.tail.lock();
-        let tail_value = tail.read();
+    /// The element dequeue would return next, without removing it.  Returns None if the queue is empty.
+    pub fn peek(&self) -> Option<T> {
+        let head = self.head.lock();
+        let stored_value = self.get_storage(head.read()).load(Ordering::Relaxed);
+        if stored_value == T::NULL {
+            return None;
+        }
+        unsafe {
+            return Some(T::from_raw(stored_value));
+        }
+    }
 
-        let storage = unsafe {
-            self.buffer
-                .as_ptr()
-                .offset(tail_value as isize)
-                .as_ref()
-                .unwrap()
-        }; //self.get_storage(tail_value as usize);
-        let stored_value = storage.load(Ordering::Relaxed);
-        if stored_value != QUEUE_U32_NULL {
+    /// An iterator that dequeues until the queue is empty.  Unlike clear, the elements are handed back, and producers waiting for room are woken.
+    pub fn drain(&self) -> Drain<'_, 'a, T> {
+        return Drain { queue: self };
+    }
+
+    /// Dequeue, blocking the thread until an element is available.
+    #[cfg(all(any(test, feature = "std"), target_os = "linux"))]
+    pub fn dequeue_wait(&self) -> T {
+        return self.dequeue_until(None).unwrap();
+    }
+
+    /// Dequeue, blocking the thread for at most `timeout`.  Returns None if the queue was still empty when it expired.
+    #[cfg(all(any(test, feature = "std"), target_os = "linux"))]
+    pub fn dequeue_timeout(&self, timeout: Duration) -> Option<T> {
+        return self.dequeue_until(Some(Instant::now() + timeout));
+    }
+
+    /// Enqueue, blocking the thread until there is room.
+    /// Returns false straight away if the value converts to the element type's NULL, which no amount of room would let in.
+    #[cfg(all(any(test, feature = "std"), target_os = "linux"))]
+    pub fn enqueue_wait(&self, value: T) -> bool {
+        if value.into_raw() == T::NULL {
             return false;
         }
-        storage.store(value, Ordering::Relaxed);
-        tail.write(tail_value.wrapping_add(1) & self.buffer_capacity_mask);
+        while !self.enqueue(value) {
+            self.not_full_waiters.fetch_add(1, Ordering::Relaxed);
+            let sequence = self.not_full.load(Ordering::Acquire);
+            // Passing through the head lock orders our registration against consumers - either they see it, or we see their dequeue.
+            drop(self.head.lock());
+            if self.enqueue(value) {
+                self.not_full_waiters.fetch_sub(1, Ordering::Relaxed);
+                return true;
+            }
+            futex_wait(&self.not_full, sequence, None);
+            self.not_full_waiters.fetch_sub(1, Ordering::Relaxed);
+        }
         return true;
     }
 
-    pub fn dequeue(&self) -> Option<u32> {
-        let mut head = self.head.lock();
-        let head_value = head.read();
-        let storage = unsafe {
-            self.buffer
-                .as_ptr()
-                .offset(head_value as isize)
-                .as_ref()
-                .unwrap()
-        }; //self.get_storage(head_value as usize);
-        let stored_value = storage.load(Ordering::Relaxed);
-        if stored_value == QUEUE_U32_NULL {
-            return None;
+    #[cfg(all(any(test, feature = "std"), target_os = "linux"))]
+    fn dequeue_until(&self, deadline: Option<Instant>) -> Option<T> {
+        loop {
+            let result = self.dequeue();
+            if result.is_some() {
+                return result;
+            }
+            self.not_empty_waiters.fetch_add(1, Ordering::Relaxed);
+            let sequence = self.not_empty.load(Ordering::Acquire);
+            // Passing through the tail lock orders our registration against producers - either they see it, or we see their enqueue.
+            drop(self.tail.lock());
+            let result = self.dequeue();
+            if result.is_some() {
+                self.not_empty_waiters.fetch_sub(1, Ordering::Relaxed);
+                return result;
+            }
+            let remaining = match deadline {
+                Some(x) => {
+                    let now = Instant::now();
+                    if now >= x {
+                        self.not_empty_waiters.fetch_sub(1, Ordering::Relaxed);
+                        return None;
+                    }
+                    Some(x - now)
+                }
+                None => None,
+            };
+            futex_wait(&self.not_empty, sequence, remaining);
+            self.not_empty_waiters.fetch_sub(1, Ordering::Relaxed);
         }
-        storage.store(QUEUE_U32_NULL, Ordering::Relaxed);
-        head.write(head_value.wrapping_add(1) & self.buffer_capacity_mask);
+    }
+}
 
-        return Some(stored_value);
+#[cfg(any(test, feature = "std"))]
+impl<T: QueueElement> Queue<'static, T> {
+    /// Allocate and own a buffer, filled with the element type's NULL.  Capacity must be a non-zero power of two, no larger than 2^31.
+    pub fn with_capacity(capacity: usize) -> Queue<'static, T> {
+        assert!(capacity.is_power_of_two() && capacity <= 1 << 31);
+        let buffer: Box<[T::Slot]> = (0..capacity).map(|_| T::Slot::new(T::NULL)).collect();
+        let buffer = Box::into_raw(buffer) as *mut T::Slot;
+        let mut queue = unsafe { Queue::from_raw(buffer, capacity) };
+        queue.owned_buffer = true;
+        return queue;
     }
 }
 
-unsafe impl<'a> Send for QueueU32<'a> {}
-unsafe impl<'a> Sync for QueueU32<'a> {}
+impl<'a, T: QueueElement> Drop for Queue<'a, T> {
+    fn drop(&mut self) {
+        #[cfg(any(test, feature = "std"))]
+        {
+            if self.owned_buffer {
+                unsafe {
+                    drop(Box::from_raw(core::ptr::slice_from_raw_parts_mut(
+                        self.buffer.as_ptr(),
+                        self.capacity as usize,
+                    )));
+                }
+            }
+        }
+    }
+}
+
+/// Iterator returned by Queue::drain.
+pub struct Drain<'q, 'a, T: QueueElement> {
+    queue: &'q Queue<'a, T>,
+}
+
+impl<'q, 'a, T: QueueElement> Iterator for Drain<'q, 'a, T> {
+    type Item = T;
+    #[inline(always)]
+    fn next(&mut self) -> Option<T> {
+        return self.queue.dequeue();
+    }
+}
+
+unsafe impl<'a, T: QueueElement + Send> Send for Queue<'a, T> {}
+unsafe impl<'a, T: QueueElement + Send> Sync for Queue<'a, T> {}
+
+mod async_queue;
+pub use async_queue::AsyncQueue;
+pub use async_queue::Dequeue;
+pub use async_queue::Enqueue;
+pub use async_queue::MAX_WAKERS;
+mod broadcast;
+pub use broadcast::BroadcastCursor;
+pub use broadcast::BroadcastQueue;
+pub use broadcast::BroadcastReceiver;
+mod deque;
+pub use deque::DequeWorker;
+pub use deque::WorkStealingDeque;
+mod lock_free;
+pub use lock_free::LockFreeQueue;
+pub use lock_free::SequencedCell;
+mod segmented;
+pub use segmented::SegmentedQueue;
+mod spsc;
+pub use spsc::SpscConsumer;
+pub use spsc::SpscProducer;
+pub use spsc::SpscQueue;
 
 #[cfg(test)]
 mod test;
@@ -0,0 +1,193 @@
+use crate::mem::Queue;
+use crate::mem::QueueElement;
+use crate::sync::Spinlock;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic;
+use core::sync::atomic::AtomicU32;
+use core::sync::atomic::Ordering;
+use core::task::Context;
+use core::task::Poll;
+use core::task::Waker;
+
+/// How many tasks can wait on each side of an AsyncQueue before further waiters fall back to being re-polled straight away.
+pub const MAX_WAKERS: usize = 32;
+
+const EMPTY: Option<Waker> = None;
+
+/// A fixed set of wakers, so registering never allocates.
+struct WakerSet {
+    wakers: Spinlock<[Option<Waker>; MAX_WAKERS]>,
+    count: AtomicU32,
+}
+
+impl WakerSet {
+    const fn new() -> WakerSet {
+        return WakerSet {
+            wakers: Spinlock::new(0, [EMPTY; MAX_WAKERS]),
+            count: AtomicU32::new(0),
+        };
+    }
+
+    /// Returns false if the set is full.
+    fn register(&self, waker: &Waker) -> bool {
+        let mut wakers = self.wakers.lock();
+        let mut empty = None;
+        for (i, slot) in wakers.iter().enumerate() {
+            match slot {
+                Some(x) => {
+                    if x.will_wake(waker) {
+                        return true;
+                    }
+                }
+                None => {
+                    if empty.is_none() {
+                        empty = Some(i);
+                    }
+                }
+            }
+        }
+        match empty {
+            Some(i) => {
+                wakers[i] = Some(waker.clone());
+                self.count.fetch_add(1, Ordering::Relaxed);
+                return true;
+            }
+            None => return false,
+        }
+    }
+
+    /// Wake every registered task.  Tasks whose future was dropped just get a spurious wake, so no element is ever left with nobody to take it.
+    fn wake_all(&self) {
+        atomic::fence(Ordering::SeqCst);
+        if self.count.load(Ordering::Relaxed) == 0 {
+            return;
+        }
+        let mut taken = [EMPTY; MAX_WAKERS];
+        {
+            let mut wakers = self.wakers.lock();
+            for (slot, waker) in taken.iter_mut().zip(wakers.iter_mut()) {
+                *slot = waker.take();
+            }
+            self.count.store(0, Ordering::Relaxed);
+        }
+        // Wake outside the lock, in case the executor polls inline.
+        for waker in taken.iter_mut() {
+            if let Some(x) = waker.take() {
+                x.wake();
+            }
+        }
+    }
+}
+
+/// A Queue with Future-based enqueue and dequeue, for use from async code.  Only `core::task` is used, so this works without std.
+/// Each side keeps a fixed set of MAX_WAKERS wakers - beyond that, waiting tasks are woken immediately and re-poll.
+pub struct AsyncQueue<'a, T: QueueElement> {
+    queue: Queue<'a, T>,
+    consumers: WakerSet,
+    producers: WakerSet,
+}
+
+impl<'a, T: QueueElement> AsyncQueue<'a, T> {
+    /// This method is a kludge to work around lack of stable const-generics, const unions, etc.
+    /// It is up to the caller to ensure that the pointer passed in is truly static, and is not mutated externally.
+    /// Capacity must be a non-zero power of two.
+    pub const unsafe fn from_static(slice: &'a *mut T::Slot, capacity: usize) -> AsyncQueue<'a, T> {
+        return AsyncQueue {
+            queue: Queue::from_static(slice, capacity),
+            consumers: WakerSet::new(),
+            producers: WakerSet::new(),
+        };
+    }
+
+    /// Enqueue without waiting, waking a waiting consumer.  Returns false if the queue is full, or the value converts to the element type's NULL.
+    pub fn try_enqueue(&self, value: T) -> bool {
+        if self.queue.enqueue(value) {
+            self.consumers.wake_all();
+            return true;
+        }
+        return false;
+    }
+
+    /// Dequeue without waiting, waking a waiting producer.  Returns None if the queue is empty.
+    pub fn try_dequeue(&self) -> Option<T> {
+        let result = self.queue.dequeue();
+        if result.is_some() {
+            self.producers.wake_all();
+        }
+        return result;
+    }
+
+    /// Resolves to true once the value has been enqueued, or straight away to false if it converts to the element type's NULL, which no amount of room would let in.
+    pub fn enqueue(&self, value: T) -> Enqueue<'_, 'a, T> {
+        return Enqueue {
+            queue: self,
+            value: value,
+        };
+    }
+
+    /// Resolves to the next element.
+    pub fn dequeue(&self) -> Dequeue<'_, 'a, T> {
+        return Dequeue { queue: self };
+    }
+}
+
+/// Future returned by AsyncQueue::enqueue.
+pub struct Enqueue<'q, 'a, T: QueueElement> {
+    queue: &'q AsyncQueue<'a, T>,
+    value: T,
+}
+
+impl<'q, 'a, T: QueueElement> Future for Enqueue<'q, 'a, T> {
+    type Output = bool;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<bool> {
+        let queue = self.queue;
+        if self.value.into_raw() == T::NULL {
+            return Poll::Ready(false);
+        }
+        if queue.try_enqueue(self.value) {
+            return Poll::Ready(true);
+        }
+        if !queue.producers.register(cx.waker()) {
+            cx.waker().wake_by_ref();
+            return Poll::Pending;
+        }
+        // A consumer may have made room between our first attempt and registering.
+        atomic::fence(Ordering::SeqCst);
+        if queue.try_enqueue(self.value) {
+            return Poll::Ready(true);
+        }
+        return Poll::Pending;
+    }
+}
+
+/// Future returned by AsyncQueue::dequeue.
+pub struct Dequeue<'q, 'a, T: QueueElement> {
+    queue: &'q AsyncQueue<'a, T>,
+}
+
+impl<'q, 'a, T: QueueElement> Future for Dequeue<'q, 'a, T> {
+    type Output = T;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        let queue = self.queue;
+        if let Some(x) = queue.try_dequeue() {
+            return Poll::Ready(x);
+        }
+        if !queue.consumers.register(cx.waker()) {
+            cx.waker().wake_by_ref();
+            return Poll::Pending;
+        }
+        // A producer may have enqueued between our first attempt and registering.
+        atomic::fence(Ordering::SeqCst);
+        if let Some(x) = queue.try_dequeue() {
+            return Poll::Ready(x);
+        }
+        return Poll::Pending;
+    }
+}
+
+unsafe impl<'a, T: QueueElement + Send> Send for AsyncQueue<'a, T> {}
+unsafe impl<'a, T: QueueElement + Send> Sync for AsyncQueue<'a, T> {}
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,148 @@
+use crate::mem::AsyncQueue;
+use crate::mem::MAX_WAKERS;
+use crate::mem::QUEUE_U32_NULL;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::AtomicU32;
+use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::Ordering;
+use core::task::Context;
+use core::task::Poll;
+use std::sync::Arc;
+use std::task::Wake;
+use std::thread;
+
+/// Unparks the thread blocked in block_on.
+struct ThreadWaker {
+    thread: thread::Thread,
+    wakes: AtomicUsize,
+}
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.wakes.fetch_add(1, Ordering::Relaxed);
+        self.thread.unpark();
+    }
+}
+
+/// Minimal single future executor.
+fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = Box::pin(future);
+    let waker = Arc::new(ThreadWaker {
+        thread: thread::current(),
+        wakes: AtomicUsize::new(0),
+    })
+    .into();
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        if let Poll::Ready(x) = future.as_mut().poll(&mut cx) {
+            return x;
+        }
+        thread::park();
+    }
+}
+
+#[test]
+fn ready_immediately() {
+    let mut buffer_local: [u32; 4] = [QUEUE_U32_NULL; 4];
+    let buffer_ptr = &mut buffer_local[0] as *mut u32 as *mut AtomicU32;
+    let q = unsafe { AsyncQueue::<u32>::from_static(&buffer_ptr, 4) };
+    block_on(async {
+        for i in 0..4 {
+            assert!(q.enqueue(i).await);
+        }
+        assert!(!q.try_enqueue(4));
+        // The sentinel is refused rather than waiting for room that would never let it in.
+        assert!(!q.enqueue(QUEUE_U32_NULL).await);
+        for i in 0..4 {
+            assert_eq!(q.dequeue().await, i);
+        }
+        assert_eq!(q.try_dequeue(), None);
+    });
+}
+
+#[test]
+fn wake_on_enqueue() {
+    let mut buffer_local: [u32; 4] = [QUEUE_U32_NULL; 4];
+    let buffer_ptr = &mut buffer_local[0] as *mut u32 as *mut AtomicU32;
+    let q = unsafe { AsyncQueue::<u32>::from_static(&buffer_ptr, 4) };
+    let counter = Arc::new(ThreadWaker {
+        thread: thread::current(),
+        wakes: AtomicUsize::new(0),
+    });
+    let waker = counter.clone().into();
+    let mut cx = Context::from_waker(&waker);
+
+    let mut dequeue = q.dequeue();
+    assert_eq!(Pin::new(&mut dequeue).poll(&mut cx), Poll::Pending);
+    // Polling again with the same waker does not register it twice.
+    assert_eq!(Pin::new(&mut dequeue).poll(&mut cx), Poll::Pending);
+    assert_eq!(counter.wakes.load(Ordering::Relaxed), 0);
+    assert!(q.try_enqueue(9));
+    assert_eq!(counter.wakes.load(Ordering::Relaxed), 1);
+    assert_eq!(Pin::new(&mut dequeue).poll(&mut cx), Poll::Ready(9));
+
+    for i in 0..4 {
+        assert!(q.try_enqueue(i));
+    }
+    let mut enqueue = q.enqueue(4);
+    assert_eq!(Pin::new(&mut enqueue).poll(&mut cx), Poll::Pending);
+    assert_eq!(q.try_dequeue(), Some(0));
+    assert_eq!(counter.wakes.load(Ordering::Relaxed), 2);
+    assert_eq!(Pin::new(&mut enqueue).poll(&mut cx), Poll::Ready(true));
+}
+
+#[test]
+fn wakers_full() {
+    let mut buffer_local: [u32; 4] = [QUEUE_U32_NULL; 4];
+    let buffer_ptr = &mut buffer_local[0] as *mut u32 as *mut AtomicU32;
+    let q = unsafe { AsyncQueue::<u32>::from_static(&buffer_ptr, 4) };
+    let counters: Vec<Arc<ThreadWaker>> = (0..MAX_WAKERS + 1)
+        .map(|_| {
+            Arc::new(ThreadWaker {
+                thread: thread::current(),
+                wakes: AtomicUsize::new(0),
+            })
+        })
+        .collect();
+    let mut futures: Vec<_> = (0..MAX_WAKERS + 1).map(|_| q.dequeue()).collect();
+    for (future, counter) in futures.iter_mut().zip(counters.iter()) {
+        let waker = counter.clone().into();
+        let mut cx = Context::from_waker(&waker);
+        assert_eq!(Pin::new(future).poll(&mut cx), Poll::Pending);
+    }
+    // The one that did not fit was asked to poll again straight away.
+    assert_eq!(counters[MAX_WAKERS].wakes.load(Ordering::Relaxed), 1);
+    assert!(q.try_enqueue(1));
+    for counter in counters.iter() {
+        assert_eq!(counter.wakes.load(Ordering::Relaxed), 1);
+    }
+}
+
+#[test]
+fn threads() {
+    const COUNT: u32 = 20000;
+    let mut buffer_local: [u32; 8] = [QUEUE_U32_NULL; 8];
+    let buffer_ptr = &mut buffer_local[0] as *mut u32 as *mut AtomicU32;
+    let q = unsafe { AsyncQueue::<u32>::from_static(&buffer_ptr, 8) };
+    let sum = thread::scope(|s| {
+        s.spawn(|| {
+            block_on(async {
+                for i in 0..COUNT {
+                    q.enqueue(i).await;
+                }
+            });
+        });
+        block_on(async {
+            let mut sum = 0u64;
+            for i in 0..COUNT {
+                let value = q.dequeue().await;
+                assert_eq!(value, i);
+                sum += value as u64;
+            }
+            sum
+        })
+    });
+    assert_eq!(sum, (COUNT as u64) * (COUNT as u64 - 1) / 2);
+    assert_eq!(q.try_dequeue(), None);
+}
@@ -0,0 +1,251 @@
+use crate::mem::SequencedCell;
+use crate::sync::Unique;
+use core::marker::PhantomData;
+use core::ptr;
+use core::sync::atomic::AtomicBool;
+use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::Ordering;
+
+/// One consumer's position in a BroadcastQueue.  Each sits on its own cache line, so consumers never contend with each other.
+/// A zeroed cursor is valid and unused.
+#[repr(C, align(64))]
+pub struct BroadcastCursor {
+    position: AtomicUsize,
+    // Claimed by a subscriber, which only sets active once position is in place.  Producers only look at active cursors.
+    claimed: AtomicBool,
+    active: AtomicBool,
+}
+
+impl BroadcastCursor {
+    pub const fn new() -> BroadcastCursor {
+        return BroadcastCursor {
+            position: AtomicUsize::new(0),
+            claimed: AtomicBool::new(false),
+            active: AtomicBool::new(false),
+        };
+    }
+}
+
+impl Default for BroadcastCursor {
+    fn default() -> BroadcastCursor {
+        return BroadcastCursor::new();
+    }
+}
+
+/// A bounded multicast ring, in the style of the LMAX disruptor.  Every published element is seen once by every subscribed consumer.
+/// Producers claim positions from a shared counter and mark each cell with its sequence number once written.  Each consumer reads through its own cursor.
+/// A producer can only reuse a cell once the slowest consumer has read it, so publish fails when that consumer is a full lap behind.
+/// It also fails if the producer of the cell's previous lap is still writing it.  Apart from that, with nobody subscribed, publishing succeeds and the elements are simply never read.
+/// The cell buffer and the cursors must be zeroed (or built with BroadcastCursor::new), and capacity must be a non-zero power of two.
+#[repr(C)]
+pub struct BroadcastQueue<'a, T: Copy> {
+    _cache_pad_0: [u8; 64],
+    buffer: Unique<SequencedCell<T>>,
+    capacity: usize,
+    buffer_capacity_mask: usize,
+    cursors: Unique<BroadcastCursor>,
+    max_consumers: usize,
+    _cache_pad_1: [u8; 64],
+    tail: AtomicUsize,
+    _cache_pad_2: [u8; 64],
+    _lifetime: PhantomData<&'a SequencedCell<T>>,
+}
+
+/// A subscription to a BroadcastQueue.  Dropping it releases the cursor, and stops it holding producers back.
+pub struct BroadcastReceiver<'q, 'a, T: Copy> {
+    queue: &'q BroadcastQueue<'a, T>,
+    cursor: &'q BroadcastCursor,
+}
+
+impl<'a, T: Copy> BroadcastQueue<'a, T> {
+    /// This method is a kludge to work around lack of stable const-generics, const unions, etc.
+    /// It is up to the caller to ensure that the pointers passed in are truly static, and are not mutated externally.
+    /// Capacity must be a non-zero power of two.  At most max_consumers receivers can be subscribed at once.
+    pub const unsafe fn from_static(
+        slice: &'a *mut SequencedCell<T>,
+        capacity: usize,
+        cursors: &'a *mut BroadcastCursor,
+        max_consumers: usize,
+    ) -> BroadcastQueue<'a, T> {
+        assert!(capacity.is_power_of_two());
+        return BroadcastQueue {
+            buffer: Unique::new(*slice),
+            capacity: capacity,
+            buffer_capacity_mask: capacity - 1,
+            cursors: Unique::new(*cursors),
+            max_consumers: max_consumers,
+            tail: AtomicUsize::new(0),
+            _cache_pad_0: [0; 64],
+            _cache_pad_1: [0; 64],
+            _cache_pad_2: [0; 64],
+            _lifetime: PhantomData,
+        };
+    }
+
+    #[inline(always)]
+    fn get_cell(&self, position: usize) -> &SequencedCell<T> {
+        return unsafe {
+            &*self
+                .buffer
+                .as_ptr()
+                .add(position & self.buffer_capacity_mask)
+        };
+    }
+
+    #[inline(always)]
+    fn get_cursor(&self, index: usize) -> &BroadcastCursor {
+        return unsafe { &*self.cursors.as_ptr().add(index) };
+    }
+
+    /// Sequence numbers are stored relative to the cell index, so a zeroed buffer means nothing published.
+    #[inline(always)]
+    fn published(&self, cell: &SequencedCell<T>, position: usize) -> bool {
+        let index = position & self.buffer_capacity_mask;
+        let sequence = cell.sequence.load(Ordering::Acquire).wrapping_add(index);
+        return sequence == position.wrapping_add(1);
+    }
+
+    /// Whether the cell for `position` is done with its previous lap - published then, or never used if this is the first lap.
+    /// Otherwise a producer claimed the previous lap's position and has yet to finish writing it, and must not be raced for the cell.
+    #[inline(always)]
+    fn writable(&self, cell: &SequencedCell<T>, position: usize) -> bool {
+        let index = position & self.buffer_capacity_mask;
+        let sequence = cell.sequence.load(Ordering::Acquire).wrapping_add(index);
+        if position < self.capacity {
+            return sequence == position;
+        }
+        return sequence == position.wrapping_sub(self.capacity).wrapping_add(1);
+    }
+
+    /// How far the slowest subscribed consumer is behind `tail`, or zero if there are none.
+    /// A consumer that subscribed after `tail` was read can be ahead of it, and does not count as behind.
+    fn max_lag(&self, tail: usize) -> usize {
+        let mut max_lag = 0;
+        for i in 0..self.max_consumers {
+            let cursor = self.get_cursor(i);
+            // Subscribe stores the position before setting active, so an active cursor's position is never stale.
+            if cursor.active.load(Ordering::SeqCst) {
+                let lag = tail.wrapping_sub(cursor.position.load(Ordering::SeqCst)) as isize;
+                if lag > max_lag {
+                    max_lag = lag;
+                }
+            }
+        }
+        return max_lag as usize;
+    }
+
+    /// Subscribe a new consumer, which sees everything published from now on.  Returns None if all cursors are in use.
+    pub fn subscribe(&self) -> Option<BroadcastReceiver<'_, 'a, T>> {
+        for i in 0..self.max_consumers {
+            let cursor = self.get_cursor(i);
+            if cursor
+                .claimed
+                .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+                .is_err()
+            {
+                continue;
+            }
+            // The position must be in place before producers can see the cursor.  The SeqCst store releases it along with active.
+            let mut position = self.tail.load(Ordering::SeqCst);
+            cursor.position.store(position, Ordering::Relaxed);
+            cursor.active.store(true, Ordering::SeqCst);
+            // A producer that checked the cursors before we were active may still claim a position up to the tail we read next.
+            // If that is a full lap past our start it could overwrite a cell we have not read, so start again from there.
+            loop {
+                let tail = self.tail.load(Ordering::SeqCst);
+                if tail.wrapping_sub(position) < self.capacity {
+                    break;
+                }
+                position = tail;
+                cursor.position.store(position, Ordering::SeqCst);
+            }
+            return Some(BroadcastReceiver {
+                queue: self,
+                cursor: cursor,
+            });
+        }
+        return None;
+    }
+
+    /// Publish to every subscribed consumer.  Returns false if the slowest consumer has not yet read the cell this would overwrite,
+    /// or the producer of the previous lap's element in that cell has yet to finish writing it.
+    pub fn publish(&self, value: T) -> bool {
+        let mut position = self.tail.load(Ordering::Relaxed);
+        loop {
+            if self.max_lag(position) >= self.capacity {
+                return false;
+            }
+            if !self.writable(self.get_cell(position), position) {
+                // Report full, unless other producers have moved the tail on since we looked.
+                let tail = self.tail.load(Ordering::Relaxed);
+                if tail == position {
+                    return false;
+                }
+                position = tail;
+                continue;
+            }
+            match self.tail.compare_exchange_weak(
+                position,
+                position.wrapping_add(1),
+                Ordering::SeqCst,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => {
+                    let cell = self.get_cell(position);
+                    unsafe {
+                        ptr::write((*cell.value.get()).as_mut_ptr(), value);
+                    }
+                    let index = position & self.buffer_capacity_mask;
+                    cell.sequence.store(
+                        position.wrapping_add(1).wrapping_sub(index),
+                        Ordering::Release,
+                    );
+                    return true;
+                }
+                Err(x) => position = x,
+            }
+        }
+    }
+
+    /// Total number of elements the ring can hold.
+    pub fn capacity(&self) -> usize {
+        return self.capacity;
+    }
+}
+
+impl<'q, 'a, T: Copy> BroadcastReceiver<'q, 'a, T> {
+    /// The next element, or None if nothing new has been published.
+    /// Elements arrive in claim order - if a producer has claimed a position but not finished writing it, later ones wait behind it.
+    pub fn receive(&mut self) -> Option<T> {
+        let position = self.cursor.position.load(Ordering::Relaxed);
+        let cell = self.queue.get_cell(position);
+        if !self.queue.published(cell, position) {
+            return None;
+        }
+        let value = unsafe { ptr::read((*cell.value.get()).as_ptr()) };
+        // Only now may producers reuse the cell.
+        self.cursor
+            .position
+            .store(position.wrapping_add(1), Ordering::SeqCst);
+        return Some(value);
+    }
+
+    /// How many published elements this consumer has yet to read.  A snapshot.
+    pub fn lag(&self) -> usize {
+        let tail = self.queue.tail.load(Ordering::Relaxed);
+        return tail.wrapping_sub(self.cursor.position.load(Ordering::Relaxed));
+    }
+}
+
+impl<'q, 'a, T: Copy> Drop for BroadcastReceiver<'q, 'a, T> {
+    fn drop(&mut self) {
+        self.cursor.active.store(false, Ordering::SeqCst);
+        self.cursor.claimed.store(false, Ordering::Release);
+    }
+}
+
+unsafe impl<'a, T: Copy + Send> Send for BroadcastQueue<'a, T> {}
+unsafe impl<'a, T: Copy + Send> Sync for BroadcastQueue<'a, T> {}
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,113 @@
+use crate::mem::queue::test::local_buffer;
+use crate::mem::BroadcastCursor;
+use crate::mem::BroadcastQueue;
+use std::thread;
+
+fn local_cursors(count: usize) -> Vec<BroadcastCursor> {
+    return (0..count).map(|_| BroadcastCursor::new()).collect();
+}
+
+#[test]
+fn every_consumer_sees_everything() {
+    let mut buffer = local_buffer::<u32>(16);
+    let buffer_ptr = buffer.as_mut_ptr();
+    let mut cursors = local_cursors(3);
+    let cursors_ptr = cursors.as_mut_ptr();
+    let q = unsafe { BroadcastQueue::from_static(&buffer_ptr, 16, &cursors_ptr, 3) };
+
+    // Nobody listening - nothing holds the producer back.
+    for i in 0..100 {
+        assert!(q.publish(i));
+    }
+
+    let mut a = q.subscribe().unwrap();
+    let mut b = q.subscribe().unwrap();
+    let c = q.subscribe().unwrap();
+    assert!(q.subscribe().is_none());
+    drop(c);
+    let mut c = q.subscribe().unwrap();
+    assert_eq!(a.receive(), None);
+
+    for _j in 0..10 {
+        for i in 0..16 {
+            assert!(q.publish(i));
+        }
+        // The slowest consumer is a full lap behind.
+        assert!(!q.publish(16));
+        assert_eq!(a.lag(), 16);
+        for i in 0..16 {
+            assert_eq!(a.receive(), Some(i));
+        }
+        assert_eq!(a.receive(), None);
+        assert!(!q.publish(16));
+        for i in 0..16 {
+            assert_eq!(b.receive(), Some(i));
+            assert_eq!(c.receive(), Some(i));
+        }
+        assert_eq!(b.receive(), None);
+        assert_eq!(c.receive(), None);
+    }
+
+    // A dropped consumer no longer holds the producer back.
+    for i in 0..16 {
+        assert!(q.publish(i));
+    }
+    for i in 0..16 {
+        assert_eq!(a.receive(), Some(i));
+        assert_eq!(b.receive(), Some(i));
+    }
+    drop(c);
+    assert!(q.publish(16));
+}
+
+#[test]
+fn producers_consumers_threads() {
+    const PRODUCERS: u64 = 3;
+    const PER_PRODUCER: u64 = 30000;
+    let mut buffer = local_buffer::<u64>(64);
+    let buffer_ptr = buffer.as_mut_ptr();
+    let mut cursors = local_cursors(4);
+    let cursors_ptr = cursors.as_mut_ptr();
+    let q = unsafe { BroadcastQueue::from_static(&buffer_ptr, 64, &cursors_ptr, 4) };
+
+    // Subscribe before anything is published, so every consumer must see every element.
+    let receivers: Vec<_> = (0..4).map(|_| q.subscribe().unwrap()).collect();
+    thread::scope(|s| {
+        for p in 0..PRODUCERS {
+            let q = &q;
+            s.spawn(move || {
+                for i in 0..PER_PRODUCER {
+                    while !q.publish((p << 32) | i) {
+                        thread::yield_now();
+                    }
+                }
+            });
+        }
+        for mut receiver in receivers {
+            s.spawn(move || {
+                let mut last = [-1i64; PRODUCERS as usize];
+                let mut sum = 0u64;
+                let mut count = 0;
+                while count < PRODUCERS * PER_PRODUCER {
+                    match receiver.receive() {
+                        Some(value) => {
+                            let p = (value >> 32) as usize;
+                            let i = (value & 0xFFFFFFFF) as i64;
+                            assert_eq!(i, last[p] + 1);
+                            last[p] = i;
+                            sum += value;
+                            count += 1;
+                        }
+                        None => thread::yield_now(),
+                    }
+                }
+                let mut expected = 0;
+                for p in 0..PRODUCERS {
+                    expected += (p << 32) * PER_PRODUCER + PER_PRODUCER * (PER_PRODUCER - 1) / 2;
+                }
+                assert_eq!(sum, expected);
+                assert_eq!(receiver.receive(), None);
+            });
+        }
+    });
+}
@@ -0,0 +1,171 @@
+use crate::mem::AtomicSlot;
+use crate::mem::QueueElement;
+use crate::sync::Unique;
+use core::marker::PhantomData;
+use core::sync::atomic;
+use core::sync::atomic::AtomicBool;
+use core::sync::atomic::AtomicIsize;
+use core::sync::atomic::Ordering;
+
+/// A bounded Chase-Lev work-stealing deque, with the memory orderings from Lê, Pop, Cohen and Zappa Nardelli's "Correct and Efficient Work-Stealing for Weak Memory Models".
+/// The single owner pushes and pops at the bottom through a `DequeWorker`, while any number of thieves `steal` from the top.
+/// Elements are kept in the same atomic slots as Queue, so a thief reading a slot the owner is overwriting is never a data race - the thief's CAS on top simply fails.
+/// Capacity must be a non-zero power of two.  The buffer does not need initializing.
+#[repr(C)]
+pub struct WorkStealingDeque<'a, T: QueueElement> {
+    _cache_pad_0: [u8; 64],
+    buffer: Unique<T::Slot>,
+    capacity: isize,
+    buffer_capacity_mask: isize,
+    _cache_pad_1: [u8; 64],
+    top: AtomicIsize,
+    _cache_pad_2: [u8; 64],
+    bottom: AtomicIsize,
+    worker_claimed: AtomicBool,
+    _cache_pad_3: [u8; 64],
+    _lifetime: PhantomData<&'a T::Slot>,
+    _element: PhantomData<T>,
+}
+
+/// The owner end of a WorkStealingDeque.  Dropping it allows a new worker to be claimed.
+pub struct DequeWorker<'q, 'a, T: QueueElement> {
+    deque: &'q WorkStealingDeque<'a, T>,
+}
+
+impl<'a, T: QueueElement> WorkStealingDeque<'a, T> {
+    /// This method is a kludge to work around lack of stable const-generics, const unions, etc.
+    /// It is up to the caller to ensure that the pointer passed in is truly static, and is not mutated externally.
+    /// Capacity must be a non-zero power of two.
+    pub const unsafe fn from_static(
+        slice: &'a *mut T::Slot,
+        capacity: usize,
+    ) -> WorkStealingDeque<'a, T> {
+        assert!(capacity.is_power_of_two());
+        return WorkStealingDeque {
+            buffer: Unique::new(*slice),
+            capacity: capacity as isize,
+            buffer_capacity_mask: capacity as isize - 1,
+            top: AtomicIsize::new(0),
+            bottom: AtomicIsize::new(0),
+            worker_claimed: AtomicBool::new(false),
+            _cache_pad_0: [0; 64],
+            _cache_pad_1: [0; 64],
+            _cache_pad_2: [0; 64],
+            _cache_pad_3: [0; 64],
+            _lifetime: PhantomData,
+            _element: PhantomData,
+        };
+    }
+
+    /// Claim the owner end.  Returns None if another worker is still alive.
+    pub fn worker(&self) -> Option<DequeWorker<'_, 'a, T>> {
+        if self.worker_claimed.swap(true, Ordering::Acquire) {
+            return None;
+        }
+        return Some(DequeWorker { deque: self });
+    }
+
+    #[inline(always)]
+    fn get_storage(&self, index: isize) -> &T::Slot {
+        return unsafe {
+            self.buffer
+                .as_ptr()
+                .offset(index & self.buffer_capacity_mask)
+                .as_ref()
+                .unwrap()
+        };
+    }
+
+    /// Take the oldest element.  Returns None if the deque is empty.
+    pub fn steal(&self) -> Option<T> {
+        loop {
+            let top = self.top.load(Ordering::Acquire);
+            atomic::fence(Ordering::SeqCst);
+            let bottom = self.bottom.load(Ordering::Acquire);
+            if top >= bottom {
+                return None;
+            }
+            let raw = self.get_storage(top).load(Ordering::Relaxed);
+            // Losing the race means another thief or the owner took this element - try the next one.
+            if self
+                .top
+                .compare_exchange(top, top + 1, Ordering::SeqCst, Ordering::Relaxed)
+                .is_ok()
+            {
+                return Some(unsafe { T::from_raw(raw) });
+            }
+        }
+    }
+
+    /// Approximate number of elements, which may be stale by the time it is read.
+    pub fn len(&self) -> usize {
+        let bottom = self.bottom.load(Ordering::Relaxed);
+        let top = self.top.load(Ordering::Relaxed);
+        return core::cmp::max(bottom - top, 0) as usize;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.len() == 0;
+    }
+}
+
+impl<'q, 'a, T: QueueElement> DequeWorker<'q, 'a, T> {
+    /// Push onto the bottom.  Returns false if the deque is full.
+    pub fn push(&mut self, value: T) -> bool {
+        let deque = self.deque;
+        let bottom = deque.bottom.load(Ordering::Relaxed);
+        let top = deque.top.load(Ordering::Acquire);
+        if bottom - top >= deque.capacity {
+            return false;
+        }
+        deque
+            .get_storage(bottom)
+            .store(value.into_raw(), Ordering::Relaxed);
+        atomic::fence(Ordering::Release);
+        deque.bottom.store(bottom + 1, Ordering::Relaxed);
+        return true;
+    }
+
+    /// Pop the most recently pushed element.  Returns None if the deque is empty.
+    pub fn pop(&mut self) -> Option<T> {
+        let deque = self.deque;
+        let bottom = deque.bottom.load(Ordering::Relaxed) - 1;
+        deque.bottom.store(bottom, Ordering::Relaxed);
+        atomic::fence(Ordering::SeqCst);
+        let top = deque.top.load(Ordering::Relaxed);
+        if top > bottom {
+            deque.bottom.store(bottom + 1, Ordering::Relaxed);
+            return None;
+        }
+        let raw = deque.get_storage(bottom).load(Ordering::Relaxed);
+        if top == bottom {
+            // The last element - race the thieves for it.
+            let won = deque
+                .top
+                .compare_exchange(top, top + 1, Ordering::SeqCst, Ordering::Relaxed)
+                .is_ok();
+            deque.bottom.store(bottom + 1, Ordering::Relaxed);
+            if !won {
+                return None;
+            }
+        }
+        return Some(unsafe { T::from_raw(raw) });
+    }
+
+    /// The deque this worker owns, for handing to thieves.
+    pub fn deque(&self) -> &'q WorkStealingDeque<'a, T> {
+        return self.deque;
+    }
+}
+
+impl<'q, 'a, T: QueueElement> Drop for DequeWorker<'q, 'a, T> {
+    fn drop(&mut self) {
+        self.deque.worker_claimed.store(false, Ordering::Release);
+    }
+}
+
+unsafe impl<'a, T: QueueElement + Send> Send for WorkStealingDeque<'a, T> {}
+unsafe impl<'a, T: QueueElement + Send> Sync for WorkStealingDeque<'a, T> {}
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,94 @@
+use crate::mem::WorkStealingDeque;
+use core::sync::atomic::AtomicBool;
+use core::sync::atomic::AtomicU32;
+use core::sync::atomic::AtomicU8;
+use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::Ordering;
+use std::thread;
+
+#[test]
+fn push_pop_steal() {
+    let mut buffer = vec![0usize; 64];
+    let buffer_ptr = buffer.as_mut_ptr() as *mut AtomicUsize;
+    let deque = unsafe { WorkStealingDeque::<usize>::from_static(&buffer_ptr, 64) };
+    let mut worker = deque.worker().unwrap();
+    assert!(deque.worker().is_none());
+    for _j in 0..10 {
+        for i in 0..64 {
+            assert!(worker.push(i));
+        }
+        assert!(!worker.push(64));
+        assert_eq!(deque.len(), 64);
+        // The owner works newest first, thieves take the oldest.
+        assert_eq!(worker.pop(), Some(63));
+        assert_eq!(deque.steal(), Some(0));
+        assert_eq!(deque.steal(), Some(1));
+        for i in (2..63).rev() {
+            assert_eq!(worker.pop(), Some(i));
+        }
+        assert_eq!(worker.pop(), None);
+        assert_eq!(deque.steal(), None);
+        assert!(deque.is_empty());
+    }
+    drop(worker);
+    assert!(deque.worker().is_some());
+}
+
+/// The owner pushes jobs and pops some of them back, while thieves steal.  Every job must run exactly once.
+fn stress(thieves: usize, jobs: u32, pop_every: u32) {
+    let mut buffer = vec![0u32; 256];
+    let buffer_ptr = buffer.as_mut_ptr() as *mut AtomicU32;
+    let deque = unsafe { WorkStealingDeque::<u32>::from_static(&buffer_ptr, 256) };
+    let runs: Vec<AtomicU8> = (0..jobs).map(|_| AtomicU8::new(0)).collect();
+    let done = AtomicBool::new(false);
+    thread::scope(|s| {
+        for _t in 0..thieves {
+            let deque = &deque;
+            let runs = &runs;
+            let done = &done;
+            s.spawn(move || loop {
+                match deque.steal() {
+                    Some(job) => {
+                        runs[job as usize].fetch_add(1, Ordering::Relaxed);
+                    }
+                    None => {
+                        if done.load(Ordering::Acquire) {
+                            break;
+                        }
+                        thread::yield_now();
+                    }
+                }
+            });
+        }
+
+        let mut worker = deque.worker().unwrap();
+        for job in 0..jobs {
+            while !worker.push(job) {
+                if let Some(x) = worker.pop() {
+                    runs[x as usize].fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            // Give the thieves a chance to run, even on a single core.
+            if job & 255 == 0 {
+                thread::yield_now();
+            }
+            if job % pop_every == 0 {
+                if let Some(x) = worker.pop() {
+                    runs[x as usize].fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+        while let Some(x) = worker.pop() {
+            runs[x as usize].fetch_add(1, Ordering::Relaxed);
+        }
+        done.store(true, Ordering::Release);
+    });
+    assert!(runs.iter().all(|x| x.load(Ordering::Relaxed) == 1));
+    assert!(deque.is_empty());
+}
+
+#[test]
+fn stress_threads() {
+    stress(3, 200000, 3);
+    stress(6, 200000, 1);
+}
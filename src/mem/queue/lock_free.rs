@@ -0,0 +1,181 @@
+use crate::sync::Unique;
+use core::cell::UnsafeCell;
+use core::marker::PhantomData;
+use core::mem;
+use core::mem::MaybeUninit;
+use core::ptr;
+use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::Ordering;
+
+/// One slot of a LockFreeQueue buffer.
+/// The sequence number is stored relative to the cell's index, so a zeroed buffer is a valid empty queue - no initialization required.
+#[repr(C)]
+pub struct SequencedCell<T> {
+    pub(super) sequence: AtomicUsize,
+    pub(super) value: UnsafeCell<MaybeUninit<T>>,
+}
+
+/// A bounded MPMC queue following Dmitry Vyukov's design, with a sequence number per cell.
+/// Producers and consumers only contend on a single CAS of the tail or head index - no locks are taken, and no thread ever waits on another.
+/// As in Vyukov's design, an operation that finds its cell part way through another thread's update reports full or empty rather than waiting for it,
+/// so under contention enqueue can fail with a consumer about to make room, and dequeue can miss a value that is about to land.
+/// Values of any `Send` type are moved in and out, and there is no reserved sentinel.  Elements still queued are dropped by `clear` and when the queue is dropped.
+/// The buffer must be zeroed, and capacity must be a non-zero power of two.
+#[repr(C)]
+pub struct LockFreeQueue<'a, T> {
+    _cache_pad_0: [u8; 64],
+    buffer: Unique<SequencedCell<T>>,
+    capacity: usize,
+    buffer_capacity_mask: usize,
+    _cache_pad_1: [u8; 64],
+    head: AtomicUsize,
+    _cache_pad_2: [u8; 64],
+    tail: AtomicUsize,
+    _cache_pad_3: [u8; 64],
+    _lifetime: PhantomData<&'a SequencedCell<T>>,
+    _element: PhantomData<T>,
+}
+
+impl<'a, T> LockFreeQueue<'a, T> {
+    /// This method is a kludge to work around lack of stable const-generics, const unions, etc.
+    /// It is up to the caller to ensure that the pointer passed in is truly static, and is not mutated externally.
+    /// Capacity must be a non-zero power of two.
+    pub const unsafe fn from_static(
+        slice: &'a *mut SequencedCell<T>,
+        capacity: usize,
+    ) -> LockFreeQueue<'a, T> {
+        assert!(capacity.is_power_of_two());
+        return LockFreeQueue {
+            buffer: Unique::new(*slice),
+            capacity: capacity,
+            buffer_capacity_mask: capacity - 1,
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            _cache_pad_0: [0; 64],
+            _cache_pad_1: [0; 64],
+            _cache_pad_2: [0; 64],
+            _cache_pad_3: [0; 64],
+            _lifetime: PhantomData,
+            _element: PhantomData,
+        };
+    }
+
+    #[inline(always)]
+    fn get_cell(&self, index: usize) -> &SequencedCell<T> {
+        return unsafe { self.buffer.as_ptr().add(index).as_ref().unwrap() };
+    }
+
+    #[inline(always)]
+    fn load_sequence(cell: &SequencedCell<T>, index: usize) -> usize {
+        return cell.sequence.load(Ordering::Acquire).wrapping_add(index);
+    }
+
+    #[inline(always)]
+    fn store_sequence(cell: &SequencedCell<T>, index: usize, sequence: usize) {
+        cell.sequence
+            .store(sequence.wrapping_sub(index), Ordering::Release);
+    }
+
+    /// Returns false if the queue is full, or a consumer has yet to finish with the next cell, in which case the value is dropped.
+    #[inline(always)]
+    pub fn enqueue(&self, value: T) -> bool {
+        return self.try_enqueue(value).is_ok();
+    }
+
+    /// Returns the value back if the queue is full, or a consumer has yet to finish with the next cell.
+    pub fn try_enqueue(&self, value: T) -> Result<(), T> {
+        let mut position = self.tail.load(Ordering::Relaxed);
+        loop {
+            let index = position & self.buffer_capacity_mask;
+            let cell = self.get_cell(index);
+            let sequence = LockFreeQueue::load_sequence(cell, index);
+            let difference = sequence.wrapping_sub(position) as isize;
+            if difference == 0 {
+                match self.tail.compare_exchange_weak(
+                    position,
+                    position.wrapping_add(1),
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        unsafe {
+                            ptr::write((*cell.value.get()).as_mut_ptr(), value);
+                        }
+                        LockFreeQueue::store_sequence(cell, index, position.wrapping_add(1));
+                        return Ok(());
+                    }
+                    Err(x) => position = x,
+                }
+            } else if difference < 0 {
+                // The cell still holds a value from the previous lap, or a consumer is part way through taking it.
+                // Report full, unless other producers have moved the tail on since we looked.
+                let tail = self.tail.load(Ordering::Relaxed);
+                if tail == position {
+                    return Err(value);
+                }
+                position = tail;
+            } else {
+                position = self.tail.load(Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Returns None if the queue is empty, or the producer of the next value has yet to finish writing it.
+    pub fn dequeue(&self) -> Option<T> {
+        let mut position = self.head.load(Ordering::Relaxed);
+        loop {
+            let index = position & self.buffer_capacity_mask;
+            let cell = self.get_cell(index);
+            let sequence = LockFreeQueue::load_sequence(cell, index);
+            let difference = sequence.wrapping_sub(position.wrapping_add(1)) as isize;
+            if difference == 0 {
+                match self.head.compare_exchange_weak(
+                    position,
+                    position.wrapping_add(1),
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        let value = unsafe { ptr::read((*cell.value.get()).as_ptr()) };
+                        LockFreeQueue::store_sequence(
+                            cell,
+                            index,
+                            position.wrapping_add(self.capacity),
+                        );
+                        return Some(value);
+                    }
+                    Err(x) => position = x,
+                }
+            } else if difference < 0 {
+                // Nothing has been written to this cell yet on this lap, or a producer is part way through writing it.
+                // Report empty, unless other consumers have moved the head on since we looked.
+                let head = self.head.load(Ordering::Relaxed);
+                if head == position {
+                    return None;
+                }
+                position = head;
+            } else {
+                position = self.head.load(Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Drop every element currently in the queue.  Safe to call while other threads are using the queue - it simply dequeues until empty.
+    pub fn clear(&self) {
+        while self.dequeue().is_some() {}
+    }
+}
+
+impl<'a, T> Drop for LockFreeQueue<'a, T> {
+    fn drop(&mut self) {
+        if mem::needs_drop::<T>() {
+            self.clear();
+        }
+    }
+}
+
+unsafe impl<'a, T: Send> Send for LockFreeQueue<'a, T> {}
+unsafe impl<'a, T: Send> Sync for LockFreeQueue<'a, T> {}
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,263 @@
+use crate::mem::queue::test::local_buffer;
+use crate::mem::LockFreeQueue;
+use core::num::NonZeroUsize;
+use std::thread;
+
+#[test]
+fn enqueue_dequeue() {
+    let mut buffer = local_buffer::<u64>(256);
+    let buffer_ptr = buffer.as_mut_ptr();
+    let q = unsafe { LockFreeQueue::from_static(&buffer_ptr, 256) };
+    for _j in 0..20 {
+        for i in 0..256 {
+            assert!(q.enqueue(i));
+        }
+        assert!(!q.enqueue(256));
+        for i in 0..256 {
+            assert_eq!(q.dequeue(), Some(i));
+        }
+        assert_eq!(q.dequeue(), None);
+    }
+}
+
+#[test]
+fn enqueue2_dequeue_wrap() {
+    let mut buffer = local_buffer::<u32>(64);
+    let buffer_ptr = buffer.as_mut_ptr();
+    let q = unsafe { LockFreeQueue::from_static(&buffer_ptr, 64) };
+    let mut expected = 0;
+    let mut next = 0;
+    for _i in 0..4096 {
+        if q.enqueue(next) {
+            next += 1;
+        }
+        if q.enqueue(next) {
+            next += 1;
+        }
+        assert_eq!(q.dequeue(), Some(expected));
+        expected += 1;
+    }
+    // Full on every iteration once it has filled, after which each dequeue makes room for one more.
+    assert_eq!(next - expected, 63);
+}
+
+#[test]
+fn threads() {
+    let mut buffer = local_buffer::<NonZeroUsize>(4096);
+    let buffer_ptr = buffer.as_mut_ptr();
+    let q = unsafe { LockFreeQueue::from_static(&buffer_ptr, 4096) };
+    for i in 0..4096 {
+        assert!(q.enqueue(NonZeroUsize::new(i + 1).unwrap()));
+    }
+    thread::scope(|s| {
+        for _i in 0..4 {
+            let q = &q;
+            s.spawn(move || {
+                let mut data = Vec::with_capacity(1024);
+                for _j in 0..256 {
+                    // Nothing waits on another thread's cell, so a value part way through being moved can read as missing, or its cell as full.
+                    for _k in 0..1024 {
+                        loop {
+                            if let Some(x) = q.dequeue() {
+                                data.push(x);
+                                break;
+                            }
+                            thread::yield_now();
+                        }
+                    }
+                    for _k in 0..1024 {
+                        let value = data.pop().unwrap();
+                        while !q.enqueue(value) {
+                            thread::yield_now();
+                        }
+                    }
+                }
+            });
+        }
+    });
+
+    let mut ints: Vec<u32> = vec![0; 4096];
+    for _i in 0..4096 {
+        ints[q.dequeue().unwrap().get() - 1] += 1;
+    }
+    assert_eq!(q.dequeue(), None);
+    assert!(ints.iter().all(|x| *x == 1));
+}
+
+/// Many producers and consumers hammering a small queue, so it is constantly both full and empty.
+/// Every value carries its producer and sequence number - consumers check per-producer ordering, and the checksum proves nothing was lost or duplicated.
+fn stress(producers: usize, consumers: usize, per_producer: usize, capacity: usize) {
+    let mut buffer = local_buffer::<u64>(capacity);
+    let buffer_ptr = buffer.as_mut_ptr();
+    let q = unsafe { LockFreeQueue::from_static(&buffer_ptr, capacity) };
+    let total = producers * per_producer;
+    let consumed = std::sync::atomic::AtomicUsize::new(0);
+
+    let (sum, count) = thread::scope(|s| {
+        for p in 0..producers {
+            let q = &q;
+            s.spawn(move || {
+                for i in 0..per_producer {
+                    let value = ((p as u64) << 32) | i as u64;
+                    while !q.enqueue(value) {
+                        thread::yield_now();
+                    }
+                }
+            });
+        }
+        let mut handles = vec![];
+        for _c in 0..consumers {
+            let q = &q;
+            let consumed = &consumed;
+            handles.push(s.spawn(move || {
+                let mut last = vec![-1i64; producers];
+                let mut sum = 0u64;
+                let mut count = 0usize;
+                while consumed.load(std::sync::atomic::Ordering::Relaxed) < total {
+                    match q.dequeue() {
+                        Some(value) => {
+                            let p = (value >> 32) as usize;
+                            let i = (value & 0xFFFFFFFF) as i64;
+                            assert!(i > last[p]);
+                            last[p] = i;
+                            sum += value;
+                            count += 1;
+                            consumed.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                        }
+                        None => thread::yield_now(),
+                    }
+                }
+                (sum, count)
+            }));
+        }
+        let mut sum = 0u64;
+        let mut count = 0usize;
+        for h in handles {
+            let (s, c) = h.join().unwrap();
+            sum += s;
+            count += c;
+        }
+        (sum, count)
+    });
+
+    let mut expected = 0u64;
+    for p in 0..producers {
+        for i in 0..per_producer {
+            expected += ((p as u64) << 32) | i as u64;
+        }
+    }
+    assert_eq!(count, total);
+    assert_eq!(sum, expected);
+    assert_eq!(q.dequeue(), None);
+}
+
+#[test]
+fn stress_balanced() {
+    stress(4, 4, 100000, 1024);
+}
+
+#[test]
+fn stress_peak_contention() {
+    stress(8, 8, 50000, 16);
+}
+
+#[test]
+fn stress_many_producers() {
+    stress(12, 2, 20000, 64);
+}
+
+#[test]
+fn stress_many_consumers() {
+    stress(2, 12, 100000, 64);
+}
+
+static DROPPED: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+/// Counts drops, so we can check nothing leaks or is dropped twice.
+struct Tracked {
+    value: Box<usize>,
+}
+
+impl Drop for Tracked {
+    fn drop(&mut self) {
+        DROPPED.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    }
+}
+
+#[test]
+fn non_copy_drop() {
+    let mut buffer = local_buffer::<Tracked>(64);
+    let buffer_ptr = buffer.as_mut_ptr();
+    {
+        let q = unsafe { LockFreeQueue::from_static(&buffer_ptr, 64) };
+        for i in 0..64 {
+            assert!(q.enqueue(Tracked { value: Box::new(i) }));
+        }
+        // A rejected value is handed back rather than lost in the queue.
+        let rejected = q.try_enqueue(Tracked {
+            value: Box::new(64),
+        });
+        assert_eq!(*rejected.as_ref().err().unwrap().value, 64);
+        assert_eq!(DROPPED.load(std::sync::atomic::Ordering::Relaxed), 0);
+        drop(rejected);
+        assert_eq!(DROPPED.load(std::sync::atomic::Ordering::Relaxed), 1);
+
+        for i in 0..10 {
+            assert_eq!(*q.dequeue().unwrap().value, i);
+        }
+        assert_eq!(DROPPED.load(std::sync::atomic::Ordering::Relaxed), 11);
+        q.clear();
+        assert_eq!(DROPPED.load(std::sync::atomic::Ordering::Relaxed), 65);
+        assert!(q.dequeue().is_none());
+
+        // The queue is still usable after clear, and drops what is left in it.
+        for i in 0..32 {
+            assert!(q.enqueue(Tracked { value: Box::new(i) }));
+        }
+    }
+    assert_eq!(DROPPED.load(std::sync::atomic::Ordering::Relaxed), 97);
+}
+
+#[test]
+fn strings_threads() {
+    let mut buffer = local_buffer::<String>(32);
+    let buffer_ptr = buffer.as_mut_ptr();
+    let q = unsafe { LockFreeQueue::from_static(&buffer_ptr, 32) };
+    let consumed = std::sync::atomic::AtomicUsize::new(0);
+    let total: usize = thread::scope(|s| {
+        for p in 0..4 {
+            let q = &q;
+            s.spawn(move || {
+                for i in 0..10000 {
+                    let mut value = format!("{}:{}", p, i);
+                    while let Err(x) = q.try_enqueue(value) {
+                        value = x;
+                        thread::yield_now();
+                    }
+                }
+            });
+        }
+        let mut handles = vec![];
+        for _c in 0..4 {
+            let q = &q;
+            let consumed = &consumed;
+            handles.push(s.spawn(move || {
+                let mut count = 0;
+                while consumed.load(std::sync::atomic::Ordering::Relaxed) < 40000 {
+                    match q.dequeue() {
+                        Some(value) => {
+                            assert!(value.contains(':'));
+                            count += 1;
+                            consumed.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                        }
+                        None => thread::yield_now(),
+                    }
+                }
+                count
+            }));
+        }
+        handles.into_iter().map(|h| h.join().unwrap()).sum()
+    });
+    assert_eq!(total, 40000);
+    assert_eq!(q.dequeue(), None);
+}
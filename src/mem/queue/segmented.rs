@@ -0,0 +1,176 @@
+use crate::mem::AtomicSlot;
+use crate::mem::MemoryPool;
+use crate::mem::QueueElement;
+use crate::sync::Spinlock;
+use core::marker::PhantomData;
+use core::mem::size_of;
+use core::ptr;
+use core::sync::atomic::AtomicPtr;
+use core::sync::atomic::Ordering;
+
+/// Starts every segment.  The element slots follow it, filling the rest of the pool block.
+#[repr(C)]
+struct SegmentHeader {
+    next: AtomicPtr<u8>,
+}
+
+/// An unbounded MPMC queue made of fixed size segments, each one block of a MemoryPool.
+/// Producers link a new segment on when the tail segment fills, and consumers return each segment to the pool once they have read all of it.
+/// Head and tail are locked like Queue's, with the lock value holding the index into the current segment.
+/// Enqueue only fails if the pool cannot supply another segment.  The pool's blocks must have room for at least one element after the segment header.
+pub struct SegmentedQueue<'p, 'a, T: QueueElement> {
+    pool: &'p MemoryPool<'a>,
+    // The first segment ever allocated.  Consumers start here, since head cannot be set up without taking the tail lock.
+    first: AtomicPtr<u8>,
+    head: Spinlock<*mut u8>,
+    tail: Spinlock<*mut u8>,
+    _element: PhantomData<T>,
+}
+
+impl<'p, 'a, T: QueueElement> SegmentedQueue<'p, 'a, T> {
+    /// Panics if the pool's blocks cannot hold the segment header and at least one element - in a static, this fails to compile.
+    pub const fn new(pool: &'p MemoryPool<'a>) -> SegmentedQueue<'p, 'a, T> {
+        assert!(pool.block_size() >= size_of::<SegmentHeader>() + size_of::<T::Slot>());
+        return SegmentedQueue {
+            pool: pool,
+            first: AtomicPtr::new(ptr::null_mut()),
+            head: Spinlock::new(0, ptr::null_mut()),
+            tail: Spinlock::new(0, ptr::null_mut()),
+            _element: PhantomData,
+        };
+    }
+
+    /// How many elements fit in one segment.
+    #[inline(always)]
+    pub fn segment_capacity(&self) -> u32 {
+        return ((self.pool.block_size() - size_of::<SegmentHeader>()) / size_of::<T::Slot>())
+            as u32;
+    }
+
+    #[inline(always)]
+    fn header(segment: *mut u8) -> &'p SegmentHeader {
+        return unsafe { &*(segment as *const SegmentHeader) };
+    }
+
+    #[inline(always)]
+    fn get_storage(segment: *mut u8, index: u32) -> &'p T::Slot {
+        return unsafe {
+            &*((segment.add(size_of::<SegmentHeader>()) as *const T::Slot).add(index as usize))
+        };
+    }
+
+    /// Pool blocks are not zeroed, so every slot is set to NULL before the segment is linked in.
+    fn new_segment(&self) -> *mut u8 {
+        let segment = unsafe { self.pool.allocate() };
+        if segment.is_null() {
+            return segment;
+        }
+        unsafe {
+            ptr::write(
+                segment as *mut SegmentHeader,
+                SegmentHeader {
+                    next: AtomicPtr::new(ptr::null_mut()),
+                },
+            );
+        }
+        for i in 0..self.segment_capacity() {
+            SegmentedQueue::<T>::get_storage(segment, i).store(T::NULL, Ordering::Relaxed);
+        }
+        return segment;
+    }
+
+    /// Returns false if the pool is exhausted, or the value converts to the element type's NULL.
+    pub fn enqueue(&self, value: T) -> bool {
+        let v = value.into_raw();
+        if v == T::NULL {
+            return false;
+        }
+
+        let mut tail = self.tail.lock();
+        let mut index = tail.read();
+        if tail.is_null() {
+            let segment = self.new_segment();
+            if segment.is_null() {
+                return false;
+            }
+            *tail = segment;
+            index = 0;
+            self.first.store(segment, Ordering::Release);
+        } else if index == self.segment_capacity() {
+            let segment = self.new_segment();
+            if segment.is_null() {
+                return false;
+            }
+            // This is the last time a producer touches the old segment - once consumers see the link they may free it.
+            SegmentedQueue::<T>::header(*tail)
+                .next
+                .store(segment, Ordering::Release);
+            *tail = segment;
+            index = 0;
+        }
+        SegmentedQueue::<T>::get_storage(*tail, index).store(v, Ordering::Release);
+        tail.write(index + 1);
+        return true;
+    }
+
+    /// Returns None if the queue is empty.
+    pub fn dequeue(&self) -> Option<T> {
+        let mut head = self.head.lock();
+        let mut index = head.read();
+        if head.is_null() {
+            let first = self.first.load(Ordering::Acquire);
+            if first.is_null() {
+                return None;
+            }
+            *head = first;
+            index = 0;
+        } else if index == self.segment_capacity() {
+            let next = SegmentedQueue::<T>::header(*head)
+                .next
+                .load(Ordering::Acquire);
+            if next.is_null() {
+                return None;
+            }
+            // Every element in the old segment has been read, and producers have moved on.
+            unsafe {
+                self.pool.deallocate(*head);
+            }
+            *head = next;
+            index = 0;
+        }
+        let stored_value = SegmentedQueue::<T>::get_storage(*head, index).load(Ordering::Acquire);
+        if stored_value == T::NULL {
+            head.write(index);
+            return None;
+        }
+        head.write(index + 1);
+        unsafe {
+            return Some(T::from_raw(stored_value));
+        }
+    }
+}
+
+impl<'p, 'a, T: QueueElement> Drop for SegmentedQueue<'p, 'a, T> {
+    /// Return every segment still held to the pool.
+    fn drop(&mut self) {
+        let mut segment = *self.head.lock();
+        if segment.is_null() {
+            segment = self.first.load(Ordering::Acquire);
+        }
+        while !segment.is_null() {
+            let next = SegmentedQueue::<T>::header(segment)
+                .next
+                .load(Ordering::Acquire);
+            unsafe {
+                self.pool.deallocate(segment);
+            }
+            segment = next;
+        }
+    }
+}
+
+unsafe impl<'p, 'a, T: QueueElement + Send> Send for SegmentedQueue<'p, 'a, T> {}
+unsafe impl<'p, 'a, T: QueueElement + Send> Sync for SegmentedQueue<'p, 'a, T> {}
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,130 @@
+use crate::mem::MemoryPool;
+use crate::mem::MemoryPoolPolicy;
+use crate::mem::SegmentedQueue;
+use core::num::NonZeroUsize;
+use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::Ordering;
+use std::thread;
+
+#[test]
+fn enqueue_dequeue() {
+    let mut buffer_local: [usize; 64] = [0; 64];
+    let buffer_ptr = &mut buffer_local[0] as *mut usize as *mut AtomicUsize;
+    let pool = unsafe { MemoryPool::from_static(256, &buffer_ptr, 64) };
+    {
+        let q = SegmentedQueue::<u32>::new(&pool);
+        // 8 bytes of header, then 62 u32 slots.
+        assert_eq!(q.segment_capacity(), 62);
+        assert_eq!(q.dequeue(), None);
+        assert!(!q.enqueue(u32::MAX));
+        assert_eq!(q.dequeue(), None);
+        for _j in 0..10 {
+            // Several segments deep - well beyond any one segment.
+            for i in 0..1000 {
+                assert!(q.enqueue(i));
+            }
+            for i in 0..1000 {
+                assert_eq!(q.dequeue(), Some(i));
+            }
+            assert_eq!(q.dequeue(), None);
+        }
+        // Consumed segments went back to the pool, so it never needed more than the burst.
+        assert!(q.enqueue(0));
+    }
+    // Dropping the queue returned its segments, so the whole pool is available again.
+    for _i in 0..64 {
+        assert_ne!(unsafe { pool.allocate() }, core::ptr::null_mut());
+    }
+}
+
+#[test]
+#[should_panic]
+fn block_too_small() {
+    let mut buffer_local: [usize; 4] = [0; 4];
+    let buffer_ptr = &mut buffer_local[0] as *mut usize as *mut AtomicUsize;
+    let pool = unsafe { MemoryPool::from_static(8, &buffer_ptr, 4) };
+    // Room for the header, but not for a single element.
+    let _q = SegmentedQueue::<u32>::new(&pool);
+}
+
+#[test]
+fn pool_exhausted() {
+    let mut buffer_local: [usize; 4] = [0; 4];
+    let buffer_ptr = &mut buffer_local[0] as *mut usize as *mut AtomicUsize;
+    // A single chunk of four blocks, and no room to map another.
+    let policy = MemoryPoolPolicy::new(4).with_max_footprint(4096);
+    let pool = unsafe { MemoryPool::from_static_with_policy(64, &buffer_ptr, 4, policy) };
+    let q = SegmentedQueue::<NonZeroUsize>::new(&pool);
+    let per_segment = q.segment_capacity() as usize;
+    assert_eq!(per_segment, 7);
+    for i in 0..4 * per_segment {
+        assert!(q.enqueue(NonZeroUsize::new(i + 1).unwrap()));
+    }
+    assert!(!q.enqueue(NonZeroUsize::new(1).unwrap()));
+    // Draining the first segment frees it up for the producer.
+    for i in 0..per_segment + 1 {
+        assert_eq!(q.dequeue().unwrap().get(), i + 1);
+    }
+    assert!(q.enqueue(NonZeroUsize::new(1000).unwrap()));
+    for i in per_segment + 1..4 * per_segment {
+        assert_eq!(q.dequeue().unwrap().get(), i + 1);
+    }
+    assert_eq!(q.dequeue().unwrap().get(), 1000);
+    assert_eq!(q.dequeue(), None);
+}
+
+#[test]
+fn bursty_threads() {
+    let mut buffer_local: Vec<usize> = vec![0; 4096];
+    let buffer_ptr = &mut buffer_local[0] as *mut usize as *mut AtomicUsize;
+    let pool = unsafe { MemoryPool::from_static(512, &buffer_ptr, 4096) };
+    let q = SegmentedQueue::<u64>::new(&pool);
+    const PER_PRODUCER: u64 = 50000;
+    let consumed = AtomicUsize::new(0);
+    let sum = thread::scope(|s| {
+        for p in 0..4u64 {
+            let q = &q;
+            s.spawn(move || {
+                for i in 0..PER_PRODUCER {
+                    assert!(q.enqueue((p << 32) | i));
+                    // Bursts, so the queue grows by many segments before draining.
+                    if i % 10000 == 0 {
+                        thread::yield_now();
+                    }
+                }
+            });
+        }
+        let mut handles = vec![];
+        for _c in 0..4 {
+            let q = &q;
+            let consumed = &consumed;
+            handles.push(s.spawn(move || {
+                let mut last = [-1i64; 4];
+                let mut sum = 0u64;
+                while consumed.load(Ordering::Relaxed) < 4 * PER_PRODUCER as usize {
+                    match q.dequeue() {
+                        Some(value) => {
+                            let p = (value >> 32) as usize;
+                            let i = (value & 0xFFFFFFFF) as i64;
+                            assert!(i > last[p]);
+                            last[p] = i;
+                            sum += value;
+                            consumed.fetch_add(1, Ordering::Relaxed);
+                        }
+                        None => thread::yield_now(),
+                    }
+                }
+                sum
+            }));
+        }
+        handles.into_iter().map(|h| h.join().unwrap()).sum::<u64>()
+    });
+    let mut expected = 0u64;
+    for p in 0..4u64 {
+        for i in 0..PER_PRODUCER {
+            expected += (p << 32) | i;
+        }
+    }
+    assert_eq!(sum, expected);
+    assert_eq!(q.dequeue(), None);
+}
@@ -0,0 +1,215 @@
+use crate::sync::Unique;
+use core::cell::UnsafeCell;
+use core::marker::PhantomData;
+use core::ptr;
+use core::sync::atomic::AtomicBool;
+use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::Ordering;
+
+/// A wait-free bounded ring buffer for exactly one producer and one consumer.
+/// Each side owns one index and keeps a cached copy of the other side's, so the shared index is only re-read when the cached one says the ring is full or empty.
+/// Access goes through the `SpscProducer` and `SpscConsumer` handles - at most one of each can exist at a time.
+/// Capacity must be a non-zero power of two.  The buffer does not need initializing.
+#[repr(C)]
+pub struct SpscQueue<'a, T> {
+    _cache_pad_0: [u8; 64],
+    buffer: Unique<T>,
+    capacity: usize,
+    buffer_capacity_mask: usize,
+    _cache_pad_1: [u8; 64],
+    head: AtomicUsize,
+    cached_tail: UnsafeCell<usize>,
+    consumer_claimed: AtomicBool,
+    _cache_pad_2: [u8; 64],
+    tail: AtomicUsize,
+    cached_head: UnsafeCell<usize>,
+    producer_claimed: AtomicBool,
+    _cache_pad_3: [u8; 64],
+    _lifetime: PhantomData<&'a T>,
+}
+
+/// The writing end of a SpscQueue.  Dropping it allows a new producer to be claimed.
+pub struct SpscProducer<'q, 'a, T> {
+    queue: &'q SpscQueue<'a, T>,
+}
+
+/// The reading end of a SpscQueue.  Dropping it allows a new consumer to be claimed.
+pub struct SpscConsumer<'q, 'a, T> {
+    queue: &'q SpscQueue<'a, T>,
+}
+
+impl<'a, T: Copy> SpscQueue<'a, T> {
+    /// This method is a kludge to work around lack of stable const-generics, const unions, etc.
+    /// It is up to the caller to ensure that the pointer passed in is truly static, and is not mutated externally.
+    /// Capacity must be a non-zero power of two.
+    pub const unsafe fn from_static(slice: &'a *mut T, capacity: usize) -> SpscQueue<'a, T> {
+        assert!(capacity.is_power_of_two());
+        return SpscQueue {
+            buffer: Unique::new(*slice),
+            capacity: capacity,
+            buffer_capacity_mask: capacity - 1,
+            head: AtomicUsize::new(0),
+            cached_tail: UnsafeCell::new(0),
+            consumer_claimed: AtomicBool::new(false),
+            tail: AtomicUsize::new(0),
+            cached_head: UnsafeCell::new(0),
+            producer_claimed: AtomicBool::new(false),
+            _cache_pad_0: [0; 64],
+            _cache_pad_1: [0; 64],
+            _cache_pad_2: [0; 64],
+            _cache_pad_3: [0; 64],
+            _lifetime: PhantomData,
+        };
+    }
+
+    /// Claim the producer end.  Returns None if another producer is still alive.
+    pub fn producer(&self) -> Option<SpscProducer<'_, 'a, T>> {
+        if self.producer_claimed.swap(true, Ordering::Acquire) {
+            return None;
+        }
+        return Some(SpscProducer { queue: self });
+    }
+
+    /// Claim the consumer end.  Returns None if another consumer is still alive.
+    pub fn consumer(&self) -> Option<SpscConsumer<'_, 'a, T>> {
+        if self.consumer_claimed.swap(true, Ordering::Acquire) {
+            return None;
+        }
+        return Some(SpscConsumer { queue: self });
+    }
+
+    /// Total number of elements the queue can hold.
+    pub fn capacity(&self) -> usize {
+        return self.capacity;
+    }
+
+    #[inline(always)]
+    fn get_slot(&self, position: usize) -> *mut T {
+        return unsafe {
+            self.buffer
+                .as_ptr()
+                .add(position & self.buffer_capacity_mask)
+        };
+    }
+
+    /// Room available to the producer, refreshing the cached head only if the cached value shows less than `wanted`.
+    #[inline(always)]
+    fn free_space(&self, tail: usize, wanted: usize) -> usize {
+        let cached_head = unsafe { &mut *self.cached_head.get() };
+        let mut free = self.capacity - tail.wrapping_sub(*cached_head);
+        if free < wanted {
+            *cached_head = self.head.load(Ordering::Acquire);
+            free = self.capacity - tail.wrapping_sub(*cached_head);
+        }
+        return free;
+    }
+
+    /// Elements available to the consumer, refreshing the cached tail only if the cached value shows less than `wanted`.
+    #[inline(always)]
+    fn available(&self, head: usize, wanted: usize) -> usize {
+        let cached_tail = unsafe { &mut *self.cached_tail.get() };
+        let mut available = cached_tail.wrapping_sub(head);
+        if available < wanted {
+            *cached_tail = self.tail.load(Ordering::Acquire);
+            available = cached_tail.wrapping_sub(head);
+        }
+        return available;
+    }
+}
+
+impl<'q, 'a, T: Copy> SpscProducer<'q, 'a, T> {
+    /// Returns false if the queue is full.
+    pub fn enqueue(&mut self, value: T) -> bool {
+        let queue = self.queue;
+        let tail = queue.tail.load(Ordering::Relaxed);
+        if queue.free_space(tail, 1) == 0 {
+            return false;
+        }
+        unsafe {
+            ptr::write(queue.get_slot(tail), value);
+        }
+        queue.tail.store(tail.wrapping_add(1), Ordering::Release);
+        return true;
+    }
+
+    /// Write as much of `values` as fits, returning how many were written.  All of them become visible to the consumer at once.
+    pub fn write(&mut self, values: &[T]) -> usize {
+        let queue = self.queue;
+        let tail = queue.tail.load(Ordering::Relaxed);
+        let count = core::cmp::min(queue.free_space(tail, values.len()), values.len());
+        if count == 0 {
+            return 0;
+        }
+        // At most two contiguous runs - up to the end of the buffer, then from the start.
+        let start = tail & queue.buffer_capacity_mask;
+        let first = core::cmp::min(count, queue.capacity - start);
+        unsafe {
+            ptr::copy_nonoverlapping(values.as_ptr(), queue.get_slot(tail), first);
+            ptr::copy_nonoverlapping(
+                values.as_ptr().add(first),
+                queue.buffer.as_ptr(),
+                count - first,
+            );
+        }
+        queue
+            .tail
+            .store(tail.wrapping_add(count), Ordering::Release);
+        return count;
+    }
+}
+
+impl<'q, 'a, T: Copy> SpscConsumer<'q, 'a, T> {
+    /// Returns None if the queue is empty.
+    pub fn dequeue(&mut self) -> Option<T> {
+        let queue = self.queue;
+        let head = queue.head.load(Ordering::Relaxed);
+        if queue.available(head, 1) == 0 {
+            return None;
+        }
+        let value = unsafe { ptr::read(queue.get_slot(head)) };
+        queue.head.store(head.wrapping_add(1), Ordering::Release);
+        return Some(value);
+    }
+
+    /// Fill as much of `values` as there are elements for, returning how many were read.
+    pub fn read(&mut self, values: &mut [T]) -> usize {
+        let queue = self.queue;
+        let head = queue.head.load(Ordering::Relaxed);
+        let count = core::cmp::min(queue.available(head, values.len()), values.len());
+        if count == 0 {
+            return 0;
+        }
+        let start = head & queue.buffer_capacity_mask;
+        let first = core::cmp::min(count, queue.capacity - start);
+        unsafe {
+            ptr::copy_nonoverlapping(queue.get_slot(head), values.as_mut_ptr(), first);
+            ptr::copy_nonoverlapping(
+                queue.buffer.as_ptr(),
+                values.as_mut_ptr().add(first),
+                count - first,
+            );
+        }
+        queue
+            .head
+            .store(head.wrapping_add(count), Ordering::Release);
+        return count;
+    }
+}
+
+impl<'q, 'a, T> Drop for SpscProducer<'q, 'a, T> {
+    fn drop(&mut self) {
+        self.queue.producer_claimed.store(false, Ordering::Release);
+    }
+}
+
+impl<'q, 'a, T> Drop for SpscConsumer<'q, 'a, T> {
+    fn drop(&mut self) {
+        self.queue.consumer_claimed.store(false, Ordering::Release);
+    }
+}
+
+unsafe impl<'a, T: Send> Send for SpscQueue<'a, T> {}
+unsafe impl<'a, T: Send> Sync for SpscQueue<'a, T> {}
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,132 @@
+use crate::mem::SpscQueue;
+use std::thread;
+
+#[test]
+fn enqueue_dequeue() {
+    let mut buffer = vec![0u32; 256];
+    let buffer_ptr = buffer.as_mut_ptr();
+    let q = unsafe { SpscQueue::from_static(&buffer_ptr, 256) };
+    let mut producer = q.producer().unwrap();
+    let mut consumer = q.consumer().unwrap();
+    for _j in 0..20 {
+        for i in 0..256 {
+            assert!(producer.enqueue(i));
+        }
+        assert!(!producer.enqueue(256));
+        for i in 0..256 {
+            assert_eq!(consumer.dequeue(), Some(i));
+        }
+        assert_eq!(consumer.dequeue(), None);
+    }
+}
+
+#[test]
+fn claim_handles() {
+    let mut buffer = vec![0u8; 16];
+    let buffer_ptr = buffer.as_mut_ptr();
+    let q = unsafe { SpscQueue::from_static(&buffer_ptr, 16) };
+    let producer = q.producer();
+    assert!(producer.is_some());
+    assert!(q.producer().is_none());
+    let consumer = q.consumer();
+    assert!(consumer.is_some());
+    assert!(q.consumer().is_none());
+    drop(producer);
+    drop(consumer);
+    assert!(q.producer().is_some());
+    assert!(q.consumer().is_some());
+}
+
+#[test]
+fn read_write_wrap() {
+    let mut buffer = vec![0u16; 64];
+    let buffer_ptr = buffer.as_mut_ptr();
+    let q = unsafe { SpscQueue::from_static(&buffer_ptr, 64) };
+    let mut producer = q.producer().unwrap();
+    let mut consumer = q.consumer().unwrap();
+    let source: Vec<u16> = (0..=u16::MAX).collect();
+    let mut next = 0;
+    let mut expected = 0u16;
+    let mut output = [0u16; 40];
+    for _i in 0..1000 {
+        next += producer.write(&source[next..next + 40]);
+        // The second write only partly fits, and lands across the end of the buffer.
+        let written = producer.write(&source[next..next + 30]);
+        assert_eq!(written, 24);
+        next += written;
+        assert_eq!(producer.write(&source[next..next + 40]), 0);
+
+        let mut read = 0;
+        while read < 64 {
+            let n = consumer.read(&mut output);
+            assert_ne!(n, 0);
+            for v in output[..n].iter() {
+                assert_eq!(*v, expected);
+                expected = expected.wrapping_add(1);
+            }
+            read += n;
+        }
+        assert_eq!(consumer.read(&mut output), 0);
+        assert_eq!(consumer.dequeue(), None);
+    }
+}
+
+#[test]
+fn threads() {
+    const COUNT: u64 = 1 << 20;
+    let mut buffer = vec![0u64; 1024];
+    let buffer_ptr = buffer.as_mut_ptr();
+    let q = unsafe { SpscQueue::from_static(&buffer_ptr, 1024) };
+    thread::scope(|s| {
+        s.spawn(|| {
+            let mut producer = q.producer().unwrap();
+            let mut batch = [0u64; 32];
+            let mut next = 0u64;
+            while next < COUNT {
+                if next & 3 == 0 {
+                    if producer.enqueue(next) {
+                        next += 1;
+                    } else {
+                        thread::yield_now();
+                    }
+                } else {
+                    let n = core::cmp::min(batch.len() as u64, COUNT - next) as usize;
+                    for (i, v) in batch[..n].iter_mut().enumerate() {
+                        *v = next + i as u64;
+                    }
+                    let written = producer.write(&batch[..n]);
+                    if written == 0 {
+                        thread::yield_now();
+                    }
+                    next += written as u64;
+                }
+            }
+        });
+        s.spawn(|| {
+            let mut consumer = q.consumer().unwrap();
+            let mut batch = [0u64; 17];
+            let mut expected = 0u64;
+            while expected < COUNT {
+                if expected & 1 == 0 {
+                    match consumer.dequeue() {
+                        Some(v) => {
+                            assert_eq!(v, expected);
+                            expected += 1;
+                        }
+                        None => thread::yield_now(),
+                    }
+                } else {
+                    let n = consumer.read(&mut batch);
+                    if n == 0 {
+                        thread::yield_now();
+                    }
+                    for v in batch[..n].iter() {
+                        assert_eq!(*v, expected);
+                        expected += 1;
+                    }
+                }
+            }
+            assert_eq!(consumer.dequeue(), None);
+        });
+    });
+}
@@ -1,9 +1,17 @@
+use crate::mem::Queue;
+use crate::mem::QueueU32;
 use crate::mem::QueueUsize;
+use crate::mem::SequencedCell;
+use crate::mem::QUEUE_U32_NULL;
 // use crate::mem::queue::Swap;
 use crate::sync::IndexSpinlock;
+use core::num::NonZeroU32;
 use core::num::NonZeroUsize;
+use core::sync::atomic::AtomicU32;
+use core::sync::atomic::AtomicU64;
 use core::sync::atomic::AtomicUsize;
 use std::thread;
+use std::time::Duration;
 use std::time::Instant;
 
 static mut BUFFER: [usize; 4096] = [0; 4096];
@@ -12,6 +20,17 @@ static mut QUEUE_PTR: *mut AtomicUsize = unsafe { &BUFFER[0] as *const usize as
 static QUEUE: QueueUsize = unsafe { QueueUsize::from_static(&QUEUE_PTR, 4096) };
 
 static LOCK: IndexSpinlock = IndexSpinlock::new(0);
+
+/// A zeroed, and so empty, buffer for the sequenced queues' tests.
+pub(super) fn local_buffer<T>(capacity: usize) -> Vec<SequencedCell<T>> {
+    let mut buffer = Vec::with_capacity(capacity);
+    unsafe {
+        core::ptr::write_bytes(buffer.as_mut_ptr(), 0, capacity);
+        buffer.set_len(capacity);
+    }
+    return buffer;
+}
+
 #[test]
 fn mpmc() {
     // assert_eq!(core::mem::size_of::<Option<NonZeroUsize>>(), core::mem::size_of::<usize>());
@@ -232,3 +251,316 @@ fn mpmc_threads2() {
     QUEUE.clear();
     // }
 }
+
+#[test]
+fn generic_elements() {
+    unsafe {
+        let mut buffer_u64: [u64; 256] = [u64::MAX; 256];
+        let buffer_ptr = &mut buffer_u64[0] as *mut u64 as *mut AtomicU64;
+        let q = Queue::<u64>::from_static(&buffer_ptr, 256);
+        // The sentinel is refused, alone or anywhere in a batch, rather than lost in an empty looking slot.
+        assert!(!q.enqueue(u64::MAX));
+        assert_eq!(q.enqueue_batch(&[1, u64::MAX]), 0);
+        assert_eq!(q.dequeue(), None);
+        for i in 0..256u64 {
+            assert!(q.enqueue(i << 40));
+        }
+        assert!(!q.enqueue(1));
+        for i in 0..256u64 {
+            assert_eq!(q.dequeue(), Some(i << 40));
+        }
+        assert_eq!(q.dequeue(), None);
+
+        let mut buffer_nz: [u32; 256] = [0; 256];
+        let buffer_ptr = &mut buffer_nz[0] as *mut u32 as *mut AtomicU32;
+        let q = Queue::<NonZeroU32>::from_static(&buffer_ptr, 256);
+        for i in 0..256u32 {
+            assert!(q.enqueue(NonZeroU32::new(0xFFFFFFFF - i).unwrap()));
+        }
+        for i in 0..256u32 {
+            assert_eq!(q.dequeue().unwrap().get(), 0xFFFFFFFF - i);
+        }
+        assert_eq!(q.dequeue(), None);
+    }
+}
+
+#[test]
+fn aliases() {
+    unsafe {
+        let mut buffer_local: [u32; 64] = [QUEUE_U32_NULL; 64];
+        let buffer_ptr = &mut buffer_local[0] as *mut u32 as *mut AtomicU32;
+        let q: QueueU32 = QueueU32::from_static(&buffer_ptr, 64);
+        // Zero is a valid element for u32 queues.
+        assert!(q.enqueue(0));
+        assert_eq!(q.dequeue(), Some(0));
+        assert_eq!(q.dequeue(), None);
+        assert!(!q.enqueue(QUEUE_U32_NULL));
+    }
+}
+
+
+#[test]
+fn batch_wrap() {
+    unsafe {
+        let mut buffer_local: [u32; 64] = [QUEUE_U32_NULL; 64];
+        let buffer_ptr = &mut buffer_local[0] as *mut u32 as *mut AtomicU32;
+        let q: QueueU32 = QueueU32::from_static(&buffer_ptr, 64);
+        let source: Vec<u32> = (0..100000).collect();
+        let mut output = [0u32; 48];
+        let mut next = 0;
+        let mut expected = 0u32;
+        for _i in 0..1000 {
+            next += q.enqueue_batch(&source[next..next + 40]);
+            // Only part of the second batch fits, and it wraps around the end of the buffer.
+            let enqueued = q.enqueue_batch(&source[next..next + 40]);
+            assert_eq!(enqueued, 24);
+            next += enqueued;
+            assert!(!q.enqueue(0));
+
+            assert_eq!(q.dequeue_batch(&mut output), 48);
+            for v in output.iter() {
+                assert_eq!(*v, expected);
+                expected += 1;
+            }
+            assert_eq!(q.dequeue_batch(&mut output[..20]), 16);
+            for v in output[..16].iter() {
+                assert_eq!(*v, expected);
+                expected += 1;
+            }
+            assert_eq!(q.dequeue_batch(&mut output), 0);
+            assert_eq!(next as u32, expected);
+        }
+    }
+}
+
+#[test]
+fn batch_threads() {
+    unsafe {
+        let mut buffer_local: [usize; 256] = [0; 256];
+        let buffer_ptr = &mut buffer_local[0] as *mut usize as *mut AtomicUsize;
+        let q = QueueUsize::from_static(&buffer_ptr, 256);
+        const PER_PRODUCER: usize = 100000;
+        let consumed = AtomicUsize::new(0);
+        let sum = thread::scope(|s| {
+            for p in 0..2usize {
+                let q = &q;
+                s.spawn(move || {
+                    let values: Vec<NonZeroUsize> = (0..PER_PRODUCER)
+                        .map(|i| NonZeroUsize::new(p * PER_PRODUCER + i + 1).unwrap())
+                        .collect();
+                    let mut sent = 0;
+                    while sent < PER_PRODUCER {
+                        let end = core::cmp::min(sent + 37, PER_PRODUCER);
+                        let n = q.enqueue_batch(&values[sent..end]);
+                        if n == 0 {
+                            thread::yield_now();
+                        }
+                        sent += n;
+                    }
+                });
+            }
+            let mut handles = vec![];
+            for _c in 0..2 {
+                let q = &q;
+                let consumed = &consumed;
+                handles.push(s.spawn(move || {
+                    let mut output = [NonZeroUsize::MIN; 29];
+                    let mut last = [0usize; 2];
+                    let mut sum = 0usize;
+                    while consumed.load(core::sync::atomic::Ordering::Relaxed) < 2 * PER_PRODUCER {
+                        let n = q.dequeue_batch(&mut output);
+                        if n == 0 {
+                            thread::yield_now();
+                        }
+                        for v in output[..n].iter() {
+                            // Each producer's values arrive in order.
+                            let p = (v.get() - 1) / PER_PRODUCER;
+                            assert!(v.get() > last[p]);
+                            last[p] = v.get();
+                            sum += v.get();
+                        }
+                        consumed.fetch_add(n, core::sync::atomic::Ordering::Relaxed);
+                    }
+                    sum
+                }));
+            }
+            let mut sum = 0;
+            for h in handles {
+                sum += h.join().unwrap();
+            }
+            sum
+        });
+        let n = 2 * PER_PRODUCER;
+        assert_eq!(sum, n * (n + 1) / 2);
+        assert_eq!(q.dequeue(), None);
+    }
+}
+
+#[test]
+fn dequeue_wait() {
+    unsafe {
+        let mut buffer_local: [u32; 16] = [QUEUE_U32_NULL; 16];
+        let buffer_ptr = &mut buffer_local[0] as *mut u32 as *mut AtomicU32;
+        let q: QueueU32 = QueueU32::from_static(&buffer_ptr, 16);
+
+        let now = Instant::now();
+        assert_eq!(q.dequeue_timeout(Duration::from_millis(50)), None);
+        assert!(now.elapsed() >= Duration::from_millis(50));
+
+        thread::scope(|s| {
+            let q = &q;
+            s.spawn(move || {
+                thread::sleep(Duration::from_millis(20));
+                assert!(q.enqueue(7));
+            });
+            assert_eq!(q.dequeue_wait(), 7);
+            s.spawn(move || {
+                thread::sleep(Duration::from_millis(20));
+                assert!(q.enqueue(8));
+            });
+            assert_eq!(q.dequeue_timeout(Duration::from_secs(10)), Some(8));
+        });
+    }
+}
+
+#[test]
+fn enqueue_wait() {
+    unsafe {
+        let mut buffer_local: [u32; 16] = [QUEUE_U32_NULL; 16];
+        let buffer_ptr = &mut buffer_local[0] as *mut u32 as *mut AtomicU32;
+        let q: QueueU32 = QueueU32::from_static(&buffer_ptr, 16);
+        for i in 0..16 {
+            assert!(q.enqueue(i));
+        }
+        // The sentinel is refused without blocking, even though the queue is full.
+        assert!(!q.enqueue_wait(QUEUE_U32_NULL));
+        thread::scope(|s| {
+            let q = &q;
+            s.spawn(move || {
+                thread::sleep(Duration::from_millis(20));
+                assert_eq!(q.dequeue(), Some(0));
+            });
+            assert!(q.enqueue_wait(16));
+        });
+        for i in 1..17 {
+            assert_eq!(q.dequeue(), Some(i));
+        }
+    }
+}
+
+#[test]
+fn blocking_threads() {
+    unsafe {
+        let mut buffer_local: [u32; 8] = [QUEUE_U32_NULL; 8];
+        let buffer_ptr = &mut buffer_local[0] as *mut u32 as *mut AtomicU32;
+        let q: QueueU32 = QueueU32::from_static(&buffer_ptr, 8);
+        const PER_THREAD: u32 = 20000;
+        let sum = thread::scope(|s| {
+            for p in 0..3u32 {
+                let q = &q;
+                s.spawn(move || {
+                    for i in 0..PER_THREAD {
+                        q.enqueue_wait(p * PER_THREAD + i);
+                    }
+                });
+            }
+            let mut handles = vec![];
+            for _c in 0..3 {
+                let q = &q;
+                handles.push(s.spawn(move || {
+                    let mut sum = 0u64;
+                    for _i in 0..PER_THREAD {
+                        sum += q.dequeue_wait() as u64;
+                    }
+                    sum
+                }));
+            }
+            let mut sum = 0;
+            for h in handles {
+                sum += h.join().unwrap();
+            }
+            sum
+        });
+        let n = 3 * PER_THREAD as u64;
+        assert_eq!(sum, n * (n - 1) / 2);
+        assert_eq!(q.dequeue(), None);
+    }
+}
+
+#[test]
+fn introspection() {
+    unsafe {
+        let mut buffer_local: [u32; 16] = [QUEUE_U32_NULL; 16];
+        let buffer_ptr = &mut buffer_local[0] as *mut u32 as *mut AtomicU32;
+        let q: QueueU32 = QueueU32::from_static(&buffer_ptr, 16);
+        assert_eq!(q.capacity(), 16);
+        for _j in 0..3 {
+            assert_eq!(q.len(), 0);
+            assert!(q.is_empty());
+            assert_eq!(q.peek(), None);
+            for i in 0..16 {
+                assert!(!q.is_full());
+                assert!(q.enqueue(i));
+                assert_eq!(q.len(), i as usize + 1);
+                assert!(!q.is_empty());
+            }
+            assert!(q.is_full());
+            assert_eq!(q.peek(), Some(0));
+            assert_eq!(q.len(), 16);
+            // Start part way round, so head and tail wrap on the next pass.
+            assert_eq!(q.dequeue(), Some(0));
+            assert_eq!(q.dequeue(), Some(1));
+            assert_eq!(q.peek(), Some(2));
+            assert_eq!(q.len(), 14);
+            let drained: Vec<u32> = q.drain().collect();
+            assert_eq!(drained, (2..16).collect::<Vec<u32>>());
+            assert!(q.enqueue(100));
+            assert_eq!(q.drain().next(), Some(100));
+        }
+    }
+}
+
+#[test]
+fn validated_construction() {
+    let buffer: Vec<AtomicU32> = (0..64).map(|_| AtomicU32::new(QUEUE_U32_NULL)).collect();
+    let q = QueueU32::from_slice(&buffer).unwrap();
+    assert_eq!(q.capacity(), 64);
+    assert!(q.enqueue(0));
+    assert_eq!(q.dequeue(), Some(0));
+
+    // Not a power of two.
+    assert!(QueueU32::from_slice(&buffer[..48]).is_none());
+    assert!(QueueU32::from_slice(&buffer[..0]).is_none());
+    // A u32 queue's buffer left zeroed, rather than filled with QUEUE_U32_NULL.
+    let zeroed: Vec<AtomicU32> = (0..64).map(|_| AtomicU32::new(0)).collect();
+    assert!(QueueU32::from_slice(&zeroed).is_none());
+    // Zeroed is right for NonZero elements.
+    let zeroed: Vec<AtomicUsize> = (0..64).map(|_| AtomicUsize::new(0)).collect();
+    assert!(QueueUsize::from_slice(&zeroed).is_some());
+}
+
+#[test]
+fn with_capacity() {
+    let q = QueueU32::with_capacity(128);
+    for _j in 0..4 {
+        for i in 0..128 {
+            assert!(q.enqueue(i));
+        }
+        assert!(!q.enqueue(128));
+        for i in 0..128 {
+            assert_eq!(q.dequeue(), Some(i));
+        }
+        assert_eq!(q.dequeue(), None);
+    }
+    let q = QueueUsize::with_capacity(1);
+    assert!(q.enqueue(NonZeroUsize::new(5).unwrap()));
+    assert!(!q.enqueue(NonZeroUsize::new(6).unwrap()));
+}
+
+#[test]
+#[should_panic]
+fn from_static_not_power_of_two() {
+    let mut buffer_local: [u32; 48] = [QUEUE_U32_NULL; 48];
+    let buffer_ptr = &mut buffer_local[0] as *mut u32 as *mut AtomicU32;
+    let _q = unsafe { QueueU32::from_static(&buffer_ptr, 48) };
+}
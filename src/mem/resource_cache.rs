@@ -0,0 +1,284 @@
+use crate::mem::ResourceHandle;
+use crate::mem::ResourceManager;
+#[cfg(any(test, feature = "std"))]
+use crate::sync::increment_yield_counter;
+use crate::sync::IndexSpinlock;
+use crate::sync::Unique;
+use core::marker::PhantomData;
+use core::sync::atomic::AtomicU32;
+use core::sync::atomic::Ordering;
+
+const EMPTY: u32 = 0;
+const LOADING: u32 = 1;
+const READY: u32 = 2;
+// A removed entry.  Lookups probe past it, inserts may reuse it.
+const TOMBSTONE: u32 = 3;
+
+#[cfg(all(debug_assertions, any(test, feature = "std")))]
+std::thread_local! {
+    // The cache and key of every loader running on this thread, innermost last, so a loader waiting on its own key is caught rather than spinning forever.
+    static ACTIVE_LOADERS: core::cell::RefCell<Vec<(usize, u64)>> = const { core::cell::RefCell::new(Vec::new()) };
+}
+
+/// 64-bit FNV-1a, for turning a path or name into a cache key.
+pub const fn hash_key(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    let mut i = 0;
+    while i < bytes.len() {
+        hash ^= bytes[i] as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+        i += 1;
+    }
+    return hash;
+}
+
+/// One slot of a ResourceCache's table.  A zeroed buffer is a valid, empty table.
+pub struct CacheEntry<T> {
+    key: u64,
+    // Only changed with the lock held, but atomic so a thread waiting on a load can watch it without the lock.
+    state: AtomicU32,
+    handle: ResourceHandle<T>,
+}
+
+/// Maps 64-bit keys to handles in a ResourceManager, so asking for the same key twice gives the same handle.
+/// The table is open addressed, and guarded by a single lock which is never held while a loader runs.
+/// Cached resources should be freed with ResourceCache::free.  The manager does not know about the cache, so a resource freed through it directly
+/// leaves its key in the table - the key reads as missing, but its entry is only reclaimed when a lookup probes it, or by `purge`.
+/// Capacity must be a non-zero power of two.
+pub struct ResourceCache<'a, T> {
+    manager: &'a ResourceManager<'a, T>,
+    entries: Unique<CacheEntry<T>>,
+    capacity_mask: usize,
+    lock: IndexSpinlock,
+    _lifetime: PhantomData<&'a T>,
+}
+
+impl<'a, T> ResourceCache<'a, T> {
+    /// This method is a kludge to work around lack of stable const-generics, const unions, etc.
+    /// It is up to the caller to ensure that the pointer passed in is truly static, and is not mutated externally.
+    /// The buffer must be zeroed.  Capacity must be a non-zero power of two.
+    pub const unsafe fn from_static(
+        manager: &'a ResourceManager<'a, T>,
+        entries: &'a *mut CacheEntry<T>,
+        capacity: usize,
+    ) -> ResourceCache<'a, T> {
+        assert!(capacity.is_power_of_two());
+        return ResourceCache {
+            manager: manager,
+            entries: Unique::new(*entries),
+            capacity_mask: capacity - 1,
+            lock: IndexSpinlock::new(0),
+            _lifetime: PhantomData,
+        };
+    }
+
+    /// The manager the cached resources are stored in.
+    pub fn manager(&self) -> &'a ResourceManager<'a, T> {
+        return self.manager;
+    }
+
+    /// Only call with the lock held.
+    #[inline(always)]
+    unsafe fn get_entry(&self, index: usize) -> &mut CacheEntry<T> {
+        return &mut *self.entries.as_ptr().add(index & self.capacity_mask);
+    }
+
+    /// May be called without the lock.
+    #[inline(always)]
+    unsafe fn get_state(&self, index: usize) -> &AtomicU32 {
+        return &*core::ptr::addr_of!(
+            (*self.entries.as_ptr().add(index & self.capacity_mask)).state
+        );
+    }
+
+    /// Keys are often hashes already, but may not be, so spread them before picking the first slot.
+    #[inline(always)]
+    fn first_slot(&self, key: u64) -> usize {
+        return (key.wrapping_mul(0x9E3779B97F4A7C15) >> 32) as usize;
+    }
+
+    /// Only call with the lock held.  Returns the entry holding the key, and the first free entry seen while probing.
+    unsafe fn find(&self, key: u64) -> (Option<usize>, Option<usize>) {
+        let start = self.first_slot(key);
+        let mut free = None;
+        for i in 0..=self.capacity_mask {
+            let index = (start + i) & self.capacity_mask;
+            let entry = self.get_entry(index);
+            let mut state = entry.state.load(Ordering::Relaxed);
+            // Resources freed through the manager leave their key behind - drop it now.
+            if state == READY && !self.manager.is_live(entry.handle) {
+                state = TOMBSTONE;
+                entry.state.store(TOMBSTONE, Ordering::Relaxed);
+            }
+            match state {
+                EMPTY => {
+                    if free.is_none() {
+                        free = Some(index);
+                    }
+                    // The key was never inserted past here.
+                    return (None, free);
+                }
+                TOMBSTONE => {
+                    if free.is_none() {
+                        free = Some(index);
+                    }
+                }
+                _ => {
+                    if entry.key == key {
+                        return (Some(index), free);
+                    }
+                }
+            }
+        }
+        return (None, free);
+    }
+
+    /// The handle for key, if it is cached and its resource has not been freed.
+    pub fn get(&self, key: u64) -> Option<ResourceHandle<T>> {
+        let _lock = self.lock.lock();
+        unsafe {
+            if let (Some(index), _) = self.find(key) {
+                let entry = self.get_entry(index);
+                if entry.state.load(Ordering::Relaxed) == READY {
+                    return Some(entry.handle);
+                }
+            }
+        }
+        return None;
+    }
+
+    /// The handle for key, running loader and storing its result if the key is not cached.
+    /// If several threads ask for the same missing key at once, only one runs its loader and the rest wait for its handle.
+    /// Returns a null handle if the table or the manager is full.  If the loader panics the key is left uncached.
+    /// The loader must not ask for its own key, directly or through another loader - it would wait on itself forever.  Debug builds with std panic instead.
+    pub fn get_or_insert_with<F: FnOnce() -> T>(&self, key: u64, loader: F) -> ResourceHandle<T> {
+        let index;
+        loop {
+            let loading;
+            {
+                let _lock = self.lock.lock();
+                unsafe {
+                    let (found, free) = self.find(key);
+                    match found {
+                        Some(x) => {
+                            let entry = self.get_entry(x);
+                            if entry.state.load(Ordering::Relaxed) == READY {
+                                return entry.handle;
+                            }
+                            loading = x;
+                        }
+                        None => match free {
+                            Some(x) => {
+                                let entry = self.get_entry(x);
+                                entry.key = key;
+                                entry.state.store(LOADING, Ordering::Relaxed);
+                                index = x;
+                                break;
+                            }
+                            None => return ResourceHandle::null(),
+                        },
+                    }
+                }
+            }
+            #[cfg(all(debug_assertions, any(test, feature = "std")))]
+            debug_assert!(
+                !ACTIVE_LOADERS.with(|x| x.borrow().contains(&(self as *const _ as usize, key))),
+                "a loader asked for its own key"
+            );
+            // Another thread is loading this key - wait for its entry to leave LOADING without holding the lock, then look it up again.
+            #[cfg(any(test, feature = "std"))]
+            let mut counter = 0;
+            while unsafe { self.get_state(loading) }.load(Ordering::Acquire) == LOADING {
+                #[cfg(any(test, feature = "std"))]
+                {
+                    counter = increment_yield_counter(counter, 2);
+                }
+                core::hint::spin_loop();
+            }
+        }
+
+        let loading = Loading {
+            cache: self,
+            index: index,
+        };
+        #[cfg(all(debug_assertions, any(test, feature = "std")))]
+        ACTIVE_LOADERS.with(|x| x.borrow_mut().push((self as *const _ as usize, key)));
+        let handle = self.manager.store(loader());
+        #[cfg(all(debug_assertions, any(test, feature = "std")))]
+        ACTIVE_LOADERS.with(|x| x.borrow_mut().pop());
+        core::mem::forget(loading);
+
+        let _lock = self.lock.lock();
+        unsafe {
+            let entry = self.get_entry(index);
+            if handle.is_null() {
+                entry.state.store(TOMBSTONE, Ordering::Release);
+            } else {
+                entry.handle = handle;
+                entry.state.store(READY, Ordering::Release);
+            }
+        }
+        return handle;
+    }
+
+    /// Remove the key, and free its resource in the manager.  Returns false if the key was not cached, or is still loading.
+    pub fn free(&self, key: u64) -> bool {
+        let handle;
+        {
+            let _lock = self.lock.lock();
+            unsafe {
+                let index = match self.find(key) {
+                    (Some(x), _) => x,
+                    (None, _) => return false,
+                };
+                let entry = self.get_entry(index);
+                if entry.state.load(Ordering::Relaxed) != READY {
+                    return false;
+                }
+                entry.state.store(TOMBSTONE, Ordering::Relaxed);
+                handle = entry.handle;
+            }
+        }
+        return self.manager.free(handle);
+    }
+
+    /// Drop every key whose resource was freed through the manager rather than ResourceCache::free.  Returns how many were dropped.
+    pub fn purge(&self) -> usize {
+        let _lock = self.lock.lock();
+        let mut count = 0;
+        for i in 0..=self.capacity_mask {
+            unsafe {
+                let entry = self.get_entry(i);
+                if entry.state.load(Ordering::Relaxed) == READY
+                    && !self.manager.is_live(entry.handle)
+                {
+                    entry.state.store(TOMBSTONE, Ordering::Relaxed);
+                    count += 1;
+                }
+            }
+        }
+        return count;
+    }
+}
+
+/// Clears a claimed entry if the loader unwinds, so waiting threads do not spin forever.
+struct Loading<'c, 'a, T> {
+    cache: &'c ResourceCache<'a, T>,
+    index: usize,
+}
+
+impl<'c, 'a, T> Drop for Loading<'c, 'a, T> {
+    fn drop(&mut self) {
+        #[cfg(all(debug_assertions, any(test, feature = "std")))]
+        ACTIVE_LOADERS.with(|x| x.borrow_mut().pop());
+        let _lock = self.cache.lock.lock();
+        unsafe {
+            self.cache
+                .get_state(self.index)
+                .store(TOMBSTONE, Ordering::Release);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,205 @@
+use crate::mem::hash_key;
+use crate::mem::CacheEntry;
+use crate::mem::ResourceCache;
+use crate::mem::ResourceData;
+use crate::mem::ResourceManager;
+use crate::mem::QUEUE_U32_NULL;
+use core::sync::atomic::AtomicU32;
+use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::Ordering;
+use std::thread;
+
+struct Asset {
+    id: u64,
+}
+
+/// Zeroed storage for a manager and a cache over it.
+struct Buffers {
+    queue: Vec<u32>,
+    data: Vec<ResourceData<Asset>>,
+    entries: Vec<CacheEntry<Asset>>,
+}
+
+impl Buffers {
+    fn new(capacity: usize, entries: usize) -> Buffers {
+        let mut buffers = Buffers {
+            queue: vec![QUEUE_U32_NULL; capacity],
+            data: Vec::with_capacity(capacity),
+            entries: Vec::with_capacity(entries),
+        };
+        unsafe {
+            core::ptr::write_bytes(buffers.data.as_mut_ptr(), 0, capacity);
+            core::ptr::write_bytes(buffers.entries.as_mut_ptr(), 0, entries);
+        }
+        return buffers;
+    }
+}
+
+#[test]
+fn hash() {
+    // Reference values for 64-bit FNV-1a.
+    assert_eq!(hash_key(b""), 0xcbf29ce484222325);
+    assert_eq!(hash_key(b"a"), 0xaf63dc4c8601ec8c);
+    const KEY: u64 = hash_key(b"textures/stone.png");
+    assert_eq!(KEY, hash_key("textures/stone.png".as_bytes()));
+}
+
+#[test]
+fn dedupe_and_free() {
+    let mut buffers = Buffers::new(16, 8);
+    let queue_ptr = buffers.queue.as_mut_ptr() as *mut AtomicU32;
+    let data_ptr = buffers.data.as_mut_ptr();
+    let entries_ptr = buffers.entries.as_mut_ptr();
+    let manager = unsafe { ResourceManager::from_static(&queue_ptr, &data_ptr, 16) };
+    let cache = unsafe { ResourceCache::from_static(&manager, &entries_ptr, 8) };
+
+    let key = hash_key(b"meshes/tree.obj");
+    assert_eq!(cache.get(key), None);
+    let a = cache.get_or_insert_with(key, || Asset { id: 1 });
+    let b = cache.get_or_insert_with(key, || panic!("loaded twice"));
+    assert_eq!(a, b);
+    assert_eq!(cache.get(key), Some(a));
+    let r = manager.retain(a).unwrap();
+    assert_eq!(manager.get(&r).id, 1);
+    manager.release(r);
+
+    assert!(cache.free(key));
+    assert!(!cache.free(key));
+    assert_eq!(cache.get(key), None);
+    assert!(manager.retain(a).is_none());
+    let c = cache.get_or_insert_with(key, || Asset { id: 2 });
+    assert!(c != a);
+
+    // Freeing through the manager leaves the key behind, reading as missing.
+    assert!(manager.free(c));
+    assert_eq!(cache.get(key), None);
+    let d = cache.get_or_insert_with(key, || Asset { id: 3 });
+    let r = manager.retain(d).unwrap();
+    assert_eq!(manager.get(&r).id, 3);
+    manager.release(r);
+}
+
+#[test]
+fn full() {
+    let mut buffers = Buffers::new(16, 4);
+    let queue_ptr = buffers.queue.as_mut_ptr() as *mut AtomicU32;
+    let data_ptr = buffers.data.as_mut_ptr();
+    let entries_ptr = buffers.entries.as_mut_ptr();
+    let manager = unsafe { ResourceManager::from_static(&queue_ptr, &data_ptr, 16) };
+    let cache = unsafe { ResourceCache::from_static(&manager, &entries_ptr, 4) };
+
+    for i in 0..4 {
+        assert!(!cache.get_or_insert_with(i, || Asset { id: i }).is_null());
+    }
+    assert!(cache.get_or_insert_with(4, || Asset { id: 4 }).is_null());
+    // Every key is still found with the table full.
+    for i in 0..4 {
+        assert!(cache.get(i).is_some());
+    }
+    // Removed entries are reused.
+    assert!(cache.free(2));
+    assert!(!cache.get_or_insert_with(4, || Asset { id: 4 }).is_null());
+    assert_eq!(cache.get(2), None);
+}
+
+#[test]
+fn purge() {
+    let mut buffers = Buffers::new(16, 8);
+    let queue_ptr = buffers.queue.as_mut_ptr() as *mut AtomicU32;
+    let data_ptr = buffers.data.as_mut_ptr();
+    let entries_ptr = buffers.entries.as_mut_ptr();
+    let manager = unsafe { ResourceManager::from_static(&queue_ptr, &data_ptr, 16) };
+    let cache = unsafe { ResourceCache::from_static(&manager, &entries_ptr, 8) };
+
+    let handles: Vec<_> = (0..4)
+        .map(|i| cache.get_or_insert_with(i, || Asset { id: i }))
+        .collect();
+    assert!(manager.free(handles[1]));
+    assert!(manager.free(handles[3]));
+    assert_eq!(cache.purge(), 2);
+    assert_eq!(cache.purge(), 0);
+    // Keys removed through the cache are not purged again.
+    assert!(cache.free(0));
+    assert_eq!(cache.purge(), 0);
+    assert_eq!(cache.get(2), Some(handles[2]));
+    assert_eq!(cache.get(3), None);
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic(expected = "its own key")]
+fn loader_reentry() {
+    let mut buffers = Buffers::new(16, 4);
+    let queue_ptr = buffers.queue.as_mut_ptr() as *mut AtomicU32;
+    let data_ptr = buffers.data.as_mut_ptr();
+    let entries_ptr = buffers.entries.as_mut_ptr();
+    let manager = unsafe { ResourceManager::from_static(&queue_ptr, &data_ptr, 16) };
+    let cache = unsafe { ResourceCache::from_static(&manager, &entries_ptr, 4) };
+
+    cache.get_or_insert_with(1, || {
+        // A different key is fine, but the nested loader then asks for the outer one.
+        cache.get_or_insert_with(2, || {
+            cache.get_or_insert_with(1, || Asset { id: 1 });
+            Asset { id: 2 }
+        });
+        Asset { id: 1 }
+    });
+}
+
+#[test]
+fn loader_panic() {
+    let mut buffers = Buffers::new(16, 4);
+    let queue_ptr = buffers.queue.as_mut_ptr() as *mut AtomicU32;
+    let data_ptr = buffers.data.as_mut_ptr();
+    let entries_ptr = buffers.entries.as_mut_ptr();
+    let manager = unsafe { ResourceManager::from_static(&queue_ptr, &data_ptr, 16) };
+    let cache = unsafe { ResourceCache::from_static(&manager, &entries_ptr, 4) };
+
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        cache.get_or_insert_with(7, || panic!("load failed"));
+    }));
+    assert!(result.is_err());
+    assert_eq!(cache.get(7), None);
+    assert!(!cache.get_or_insert_with(7, || Asset { id: 7 }).is_null());
+}
+
+#[test]
+fn threads() {
+    let mut buffers = Buffers::new(64, 64);
+    let queue_ptr = buffers.queue.as_mut_ptr() as *mut AtomicU32;
+    let data_ptr = buffers.data.as_mut_ptr();
+    let entries_ptr = buffers.entries.as_mut_ptr();
+    let manager = unsafe { ResourceManager::from_static(&queue_ptr, &data_ptr, 64) };
+    let cache = unsafe { ResourceCache::from_static(&manager, &entries_ptr, 64) };
+    let loads = AtomicUsize::new(0);
+
+    let handles: Vec<Vec<_>> = thread::scope(|s| {
+        let workers: Vec<_> = (0..4)
+            .map(|_| {
+                s.spawn(|| {
+                    let mut handles = Vec::new();
+                    for key in 0..32 {
+                        handles.push(cache.get_or_insert_with(key, || {
+                            loads.fetch_add(1, Ordering::Relaxed);
+                            // Give the other threads a chance to pile up on this key.
+                            thread::yield_now();
+                            Asset { id: key }
+                        }));
+                    }
+                    handles
+                })
+            })
+            .collect();
+        workers.into_iter().map(|x| x.join().unwrap()).collect()
+    });
+
+    assert_eq!(loads.load(Ordering::Relaxed), 32);
+    for other in handles.iter() {
+        assert_eq!(other, &handles[0]);
+    }
+    for (key, handle) in handles[0].iter().enumerate() {
+        let r = manager.retain(*handle).unwrap();
+        assert_eq!(manager.get(&r).id, key as u64);
+        manager.release(r);
+    }
+}
@@ -1,31 +1,127 @@
+use crate::mem::QueueElement;
 use crate::mem::QueueU32;
 use crate::sync::Unique;
 use core::marker::PhantomData;
 use core::mem::MaybeUninit;
 use core::num::NonZeroU32;
+use core::ops::Deref;
 use core::ptr;
 use core::sync::atomic::AtomicU32;
+use core::sync::atomic::AtomicU64;
 use core::sync::atomic::Ordering;
 const INITIALIZED: u32 = 1; //'in use' flag
 const UNIQUE_OFFSET: u32 = 2; // unique value
 
+// Slot lifecycle.  The object is only constructed in READY.
+const RESERVED: u32 = 0;
+const LOADING: u32 = 1;
+const READY: u32 = 2;
+const FAILED: u32 = 3;
+// A complete or fail call owns the slot while it writes the result.
+const COMPLETING: u32 = 4;
+
+/// A slot holds one value, and is either addressed by handles, or holds a replacement value for another slot.
+/// ref_count counts references to the value, plus one while it is the current value of a handle.  slot_count keeps the slot itself from being reused:
+/// one for the value, and one more for a handle slot until its handle is freed.
 pub struct ResourceData<T> {
     data: MaybeUninit<T>,
     ref_count: AtomicU32,
     unique_id: AtomicU32,
+    state: AtomicU32,
+    error: AtomicU32,
+    slot_count: AtomicU32,
+    // The slot holding the handle's current value - this slot until the value is replaced.
+    forward: AtomicU32,
+    version: AtomicU32,
 }
 
-#[repr(C)]
+/// Where a stored object is in its lifecycle.  Handles from reserve start out Reserved, and only Ready objects can be retained.
 #[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
-pub struct ResourceHandle {
+pub enum ResourceState {
+    /// Handed out by reserve, but nothing has been loaded yet.
+    Reserved,
+    /// A loader has claimed the object with start_loading.
+    Loading,
+    Ready,
+    /// Loading failed, with the error code passed to fail.
+    Failed(u32),
+}
+
+/// Identifies an object stored in a ResourceManager<T>.  The type parameter stops a handle being passed to a manager for another type.
+/// The top 8 bits of the index hold the id of the manager that issued it, so managers of the same type given different ids reject each other's handles.
+/// Still 64 bits in total.
+#[repr(C)]
+pub struct ResourceHandle<T> {
     index: u32,
     unique: u32,
+    _type: PhantomData<fn() -> T>,
 }
-impl ResourceHandle {
+impl<T> ResourceHandle<T> {
     pub fn is_null(self) -> bool {
         return self.index == REF_NULL;
     }
+
+    /// The id of the manager that issued this handle.
+    pub fn manager_id(self) -> u8 {
+        return (self.index >> MANAGER_ID_SHIFT) as u8;
+    }
+
+    pub(crate) const fn null() -> ResourceHandle<T> {
+        return ResourceHandle {
+            index: REF_NULL,
+            unique: 0,
+            _type: PhantomData,
+        };
+    }
+}
+
+// Derives would require T: Copy etc, even though no T is stored.
+impl<T> Copy for ResourceHandle<T> {}
+impl<T> Clone for ResourceHandle<T> {
+    fn clone(&self) -> ResourceHandle<T> {
+        *self
+    }
+}
+impl<T> PartialEq for ResourceHandle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        return self.index == other.index && self.unique == other.unique;
+    }
+}
+impl<T> Eq for ResourceHandle<T> {}
+impl<T> core::hash::Hash for ResourceHandle<T> {
+    fn hash<H: core::hash::Hasher>(&self, state: &mut H) {
+        self.index.hash(state);
+        self.unique.hash(state);
+    }
+}
+impl<T> core::fmt::Debug for ResourceHandle<T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        return f
+            .debug_struct("ResourceHandle")
+            .field("index", &self.index)
+            .field("unique", &self.unique)
+            .finish();
+    }
+}
+
+// Valid handles never use REF_NULL as an index, and the null handle's unique is zero, so all ones is free to mark an empty slot.
+unsafe impl<T> QueueElement for ResourceHandle<T> {
+    type Slot = AtomicU64;
+    const NULL: u64 = u64::MAX;
+    #[inline(always)]
+    fn into_raw(self) -> u64 {
+        return ((self.unique as u64) << 32) | self.index as u64;
+    }
+    #[inline(always)]
+    unsafe fn from_raw(raw: u64) -> ResourceHandle<T> {
+        return ResourceHandle {
+            index: raw as u32,
+            unique: (raw >> 32) as u32,
+            _type: PhantomData,
+        };
+    }
 }
+
 #[derive(Debug, Hash)]
 pub struct ResourceRef<'a, T> {
     index: NonZeroU32,
@@ -41,6 +137,10 @@ impl<'a, T> PartialEq for ResourceRef<'a, T> {
 impl<'a, T> Eq for ResourceRef<'a, T> {}
 
 const REF_NULL: u32 = 0xFFFFFFFF;
+const MANAGER_ID_SHIFT: u32 = 24;
+/// Slot indices live below the manager id, so a manager holds fewer than 2^24 objects.
+pub const MAX_RESOURCE_CAPACITY: u32 = 1 << MANAGER_ID_SHIFT;
+const SLOT_MASK: u32 = MAX_RESOURCE_CAPACITY - 1;
 const NON_NULL_BIT: u32 = 1 << 31;
 const REF_MASK: u32 = !NON_NULL_BIT;
 // impl<'a, T> MaybeNull for ResourceRef<'a, T> {
@@ -75,26 +175,85 @@ pub struct ResourceManager<'a, T> {
     buffer: Unique<ResourceData<T>>,
 
     free_queue: QueueU32<'a>,
+    // Slots whose object is waiting for collect to drop it.  None to drop on release.
+    destroy_queue: Option<QueueU32<'a>>,
     high_water_mark: AtomicU32,
     capacity: u32,
+    id: u32,
     _lifetime: PhantomData<&'a T>,
 }
 
 impl<'a, T> ResourceManager<'a, T> {
+    /// Capacity must be a power of two, less than MAX_RESOURCE_CAPACITY.  Handles are issued with manager id 0.
     pub const unsafe fn from_static(
         free_queue: &'a *mut AtomicU32,
         buffer: &'a *mut ResourceData<T>,
         capacity: u32,
     ) -> ResourceManager<'a, T> {
+        return ResourceManager::from_static_with_id(free_queue, buffer, capacity, 0);
+    }
+
+    /// Like from_static, but handles carry the given manager id, and handles with any other id are rejected.
+    /// Give each manager of the same T a different id to keep their handles apart.
+    pub const unsafe fn from_static_with_id(
+        free_queue: &'a *mut AtomicU32,
+        buffer: &'a *mut ResourceData<T>,
+        capacity: u32,
+        id: u8,
+    ) -> ResourceManager<'a, T> {
+        return ResourceManager::from_parts(free_queue, None, buffer, capacity, id);
+    }
+
+    /// Like from_static_with_id, but objects are not dropped by whichever thread releases the last reference.
+    /// Instead they wait in the destroy queue, which must have the same capacity, until collect is called.
+    pub const unsafe fn from_static_deferred(
+        free_queue: &'a *mut AtomicU32,
+        destroy_queue: &'a *mut AtomicU32,
+        buffer: &'a *mut ResourceData<T>,
+        capacity: u32,
+        id: u8,
+    ) -> ResourceManager<'a, T> {
+        return ResourceManager::from_parts(
+            free_queue,
+            Some(QueueU32::from_static(destroy_queue, capacity as usize)),
+            buffer,
+            capacity,
+            id,
+        );
+    }
+
+    const unsafe fn from_parts(
+        free_queue: &'a *mut AtomicU32,
+        destroy_queue: Option<QueueU32<'a>>,
+        buffer: &'a *mut ResourceData<T>,
+        capacity: u32,
+        id: u8,
+    ) -> ResourceManager<'a, T> {
+        assert!(capacity < MAX_RESOURCE_CAPACITY);
         return ResourceManager::<'a, T> {
             buffer: Unique::<ResourceData<T>>::new(*buffer),
             free_queue: QueueU32::from_static(free_queue, capacity as usize),
+            destroy_queue: destroy_queue,
             capacity: capacity,
             high_water_mark: AtomicU32::new(0),
+            id: id as u32,
             _lifetime: PhantomData,
         };
     }
 
+    /// The slot a handle refers to, or None if it is null, or was issued by a different manager.
+    #[inline(always)]
+    fn slot_of(&self, handle: ResourceHandle<T>) -> Option<u32> {
+        if handle.index == REF_NULL || handle.index >> MANAGER_ID_SHIFT != self.id {
+            return None;
+        }
+        let slot = handle.index & SLOT_MASK;
+        if slot >= self.capacity {
+            return None;
+        }
+        return Some(slot);
+    }
+
     unsafe fn get_data(&self, index: u32) -> &mut ResourceData<T> {
         return self
             .buffer
@@ -109,6 +268,27 @@ impl<'a, T> ResourceManager<'a, T> {
         data.ref_count.fetch_add(1, Ordering::Release);
     }
 
+    /// Take a reference only if the slot still holds an object.  A slot whose count has reached zero has already been dropped and queued for reuse,
+    /// so bumping it back up and down again would drop it twice.
+    unsafe fn try_increment_ref_count(&self, index: u32) -> bool {
+        let data = self.get_data(index);
+        let mut count = data.ref_count.load(Ordering::Relaxed);
+        loop {
+            if count == 0 {
+                return false;
+            }
+            match data.ref_count.compare_exchange_weak(
+                count,
+                count + 1,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return true,
+                Err(x) => count = x,
+            }
+        }
+    }
+
     unsafe fn decrement_ref_count(&self, index: u32) {
         let data = self.get_data(index);
         let previous = data.ref_count.fetch_sub(1, Ordering::AcqRel);
@@ -116,17 +296,32 @@ impl<'a, T> ResourceManager<'a, T> {
         // There is no possible way to get another reference if this was the last one.  We MUST have already incremented the unique value.
         if previous == 1 {
             // Sledgehammer this into mutable - we've protected it behind an atomic ref count.
-            ptr::drop_in_place(data.data.as_mut_ptr());
+            // Reserved, loading and failed slots never had an object constructed.
+            if data.state.load(Ordering::Acquire) == READY {
+                if let Some(queue) = &self.destroy_queue {
+                    // Each slot is queued at most once before collect frees it, so this cannot fill up.
+                    queue.enqueue(index);
+                    return;
+                }
+                ptr::drop_in_place(data.data.as_mut_ptr());
+            }
+
+            self.release_slot(index);
+        }
+    }
 
+    unsafe fn release_slot(&self, index: u32) {
+        let data = self.get_data(index);
+        if data.slot_count.fetch_sub(1, Ordering::AcqRel) == 1 {
             self.free_queue.enqueue(index as u32);
         }
     }
 
-    /// Store a T in the resource manager.  If space exists, this returns a handle to the object.  Otherwise returns None.
-    pub fn store(&self, obj: T) -> ResourceHandle {
+    /// Take an unused slot, returning its index and the unique value for the new handle.  The caller sets the value's ref count once it is written.
+    /// Slots for replacement values are not addressed by handles, so they leave the unique value without the INITIALIZED flag.
+    fn allocate(&self, state: u32, for_handle: bool) -> Option<(u32, u32)> {
         let tmp = self.free_queue.dequeue();
         let index;
-        let unique;
         let data;
         if tmp.is_none() {
             // Assume this operation is going to succeed by incrementing the counter.
@@ -135,41 +330,309 @@ impl<'a, T> ResourceManager<'a, T> {
             if next >= self.capacity {
                 // Ensure the counter does not overflow by continually incrementing.
                 self.high_water_mark.store(self.capacity, Ordering::Relaxed);
-                // return None;
-                return ResourceHandle {
-                    index: REF_NULL,
-                    unique: 0,
-                };
+                return None;
             }
 
             index = next;
-            unique = INITIALIZED;
             data = unsafe { self.get_data(index) };
+            data.unique_id.store(0, Ordering::Relaxed);
         } else {
             index = tmp.unwrap();
             data = unsafe { self.get_data(index) };
-            unique = data.unique_id.load(Ordering::Acquire) | INITIALIZED;
         }
+        data.state.store(state, Ordering::Relaxed);
+        data.forward.store(index, Ordering::Relaxed);
+        data.version.store(0, Ordering::Relaxed);
+        if !for_handle {
+            data.slot_count.store(1, Ordering::Relaxed);
+            return Some((index, data.unique_id.load(Ordering::Relaxed)));
+        }
+        data.slot_count.store(2, Ordering::Relaxed);
+        let unique = data.unique_id.load(Ordering::Relaxed) | INITIALIZED;
         data.unique_id.store(unique, Ordering::Release);
+        return Some((index, unique));
+    }
+
+    #[inline(always)]
+    fn make_handle(&self, index: u32, unique: u32) -> ResourceHandle<T> {
+        return ResourceHandle {
+            index: (self.id << MANAGER_ID_SHIFT) | index,
+            unique: unique,
+            _type: PhantomData,
+        };
+    }
+
+    /// Store a T in the resource manager.  If space exists, this returns a handle to the object.  Otherwise returns a null handle.
+    pub fn store(&self, obj: T) -> ResourceHandle<T> {
+        let (index, unique) = match self.allocate(READY, true) {
+            Some(x) => x,
+            None => return ResourceHandle::null(),
+        };
+        let data = unsafe { self.get_data(index) };
         data.data = MaybeUninit::new(obj);
 
         data.ref_count.store(1, Ordering::Release);
 
-        return ResourceHandle {
-            index: index,
-            unique: unique,
+        return self.make_handle(index, unique);
+    }
+
+    /// Hand out a handle to an object that does not exist yet.  It cannot be retained until complete is called.
+    /// Returns a null handle if the manager is full.
+    pub fn reserve(&self) -> ResourceHandle<T> {
+        let (index, unique) = match self.allocate(RESERVED, true) {
+            Some(x) => x,
+            None => return ResourceHandle::null(),
         };
+        let data = unsafe { self.get_data(index) };
+        data.ref_count.store(1, Ordering::Release);
+        return self.make_handle(index, unique);
+    }
+
+    /// Take a reference to the handle slot's own value whatever its state, so the slot cannot be reused while it is held.
+    /// Fails once the value has been replaced, which is fine for the lifecycle calls - a replaced object is already Ready.
+    fn pin(&self, handle: ResourceHandle<T>) -> Option<u32> {
+        let index = self.slot_of(handle)?;
+        unsafe {
+            if !self.try_increment_ref_count(index) {
+                return None;
+            }
+            if self.get_data(index).unique_id.load(Ordering::Acquire) != handle.unique {
+                self.decrement_ref_count(index);
+                return None;
+            }
+        }
+        return Some(index);
+    }
+
+    /// Take a reference to the handle's current value, returning the slot holding it.
+    fn resolve(&self, handle: ResourceHandle<T>) -> Option<u32> {
+        let index = self.slot_of(handle)?;
+        let data = unsafe { self.get_data(index) };
+        loop {
+            if data.unique_id.load(Ordering::Acquire) != handle.unique {
+                return None;
+            }
+            let target = data.forward.load(Ordering::Acquire);
+            if target >= self.capacity {
+                return None;
+            }
+            unsafe {
+                if !self.try_increment_ref_count(target) {
+                    // Either the handle is mid-store or being freed, or the value was replaced after we read forward.
+                    if data.forward.load(Ordering::Acquire) == target {
+                        return None;
+                    }
+                    continue;
+                }
+                // The target may have been retired and reused between reading forward and taking the reference.
+                if data.unique_id.load(Ordering::Acquire) != handle.unique {
+                    self.decrement_ref_count(target);
+                    return None;
+                }
+                if data.forward.load(Ordering::Acquire) != target {
+                    self.decrement_ref_count(target);
+                    continue;
+                }
+            }
+            return Some(target);
+        }
+    }
+
+    /// Move a reserved object to Loading, so other loaders can see it is taken.  Returns false if it was not Reserved, or the handle is stale.
+    pub fn start_loading(&self, handle: ResourceHandle<T>) -> bool {
+        let index = match self.pin(handle) {
+            Some(x) => x,
+            None => return false,
+        };
+        unsafe {
+            let result = self
+                .get_data(index)
+                .state
+                .compare_exchange(RESERVED, LOADING, Ordering::AcqRel, Ordering::Relaxed)
+                .is_ok();
+            self.decrement_ref_count(index);
+            return result;
+        }
+    }
+
+    /// Claim a reserved or loading slot for writing its result.
+    unsafe fn begin_completing(&self, index: u32) -> bool {
+        let state = &self.get_data(index).state;
+        let mut current = state.load(Ordering::Relaxed);
+        while current == RESERVED || current == LOADING {
+            match state.compare_exchange_weak(
+                current,
+                COMPLETING,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return true,
+                Err(x) => current = x,
+            }
+        }
+        return false;
+    }
+
+    /// Construct a reserved or loading object, making it Ready.  Gives the value back if the handle is stale, or the object was already completed or failed.
+    /// If the handle was freed in the meantime, the value is dropped with the last reference as usual.
+    pub fn complete(&self, handle: ResourceHandle<T>, value: T) -> Result<(), T> {
+        let index = match self.pin(handle) {
+            Some(x) => x,
+            None => return Err(value),
+        };
+        unsafe {
+            if !self.begin_completing(index) {
+                self.decrement_ref_count(index);
+                return Err(value);
+            }
+            let data = self.get_data(index);
+            data.data = MaybeUninit::new(value);
+            data.state.store(READY, Ordering::Release);
+            self.decrement_ref_count(index);
+        }
+        return Ok(());
+    }
+
+    /// Mark a reserved or loading object as Failed, with an error code of the caller's choosing.
+    /// Returns false if the handle is stale, or the object was already completed or failed.
+    pub fn fail(&self, handle: ResourceHandle<T>, error: u32) -> bool {
+        let index = match self.pin(handle) {
+            Some(x) => x,
+            None => return false,
+        };
+        unsafe {
+            let result = self.begin_completing(index);
+            if result {
+                let data = self.get_data(index);
+                data.error.store(error, Ordering::Relaxed);
+                data.state.store(FAILED, Ordering::Release);
+            }
+            self.decrement_ref_count(index);
+            return result;
+        }
+    }
+
+    /// The state of the object, or None if the handle is stale.
+    pub fn state(&self, handle: ResourceHandle<T>) -> Option<ResourceState> {
+        let index = self.resolve(handle)?;
+        unsafe {
+            let data = self.get_data(index);
+            let state = match data.state.load(Ordering::Acquire) {
+                RESERVED => ResourceState::Reserved,
+                // Mid-complete still reads as loading - it is not ready to retain yet.
+                LOADING | COMPLETING => ResourceState::Loading,
+                READY => ResourceState::Ready,
+                _ => ResourceState::Failed(data.error.load(Ordering::Relaxed)),
+            };
+            self.decrement_ref_count(index);
+            return Some(state);
+        }
+    }
+
+    /// Drop up to budget objects waiting in the destroy queue, on the calling thread, returning how many were dropped.
+    /// Does nothing unless the manager was made with from_static_deferred.
+    pub fn collect(&self, budget: usize) -> usize {
+        let queue = match &self.destroy_queue {
+            Some(x) => x,
+            None => return 0,
+        };
+        let mut count = 0;
+        while count < budget {
+            let index = match queue.dequeue() {
+                Some(x) => x,
+                None => break,
+            };
+            unsafe {
+                ptr::drop_in_place(self.get_data(index).data.as_mut_ptr());
+                self.release_slot(index);
+            }
+            count += 1;
+        }
+        return count;
+    }
+
+    /// Swap in a new value for a Ready object.  References already taken keep the old value, which is dropped once they are all released,
+    /// while retains from now on get the new value.  Gives the value back if the handle is stale, the object is not Ready, or the manager is full.
+    pub fn replace(&self, handle: ResourceHandle<T>, value: T) -> Result<(), T> {
+        if !self.is_live(handle) {
+            return Err(value);
+        }
+        let (slot, _) = match self.allocate(READY, false) {
+            Some(x) => x,
+            None => return Err(value),
+        };
+        unsafe {
+            let data = self.get_data(slot);
+            data.data = MaybeUninit::new(value);
+            // This reference is the link from the handle.
+            data.ref_count.store(1, Ordering::Release);
+            let forward = &self.get_data(handle.index & SLOT_MASK).forward;
+            loop {
+                let old = self.resolve(handle);
+                let ready = match old {
+                    Some(x) => self.get_data(x).state.load(Ordering::Acquire) == READY,
+                    None => false,
+                };
+                if !ready {
+                    if let Some(x) = old {
+                        self.decrement_ref_count(x);
+                    }
+                    // Never published, so nobody else can see it.
+                    data.ref_count.store(0, Ordering::Relaxed);
+                    let value = ptr::read(data.data.as_ptr());
+                    self.release_slot(slot);
+                    return Err(value);
+                }
+                let old = old.unwrap();
+                let swapped = forward
+                    .compare_exchange(old, slot, Ordering::AcqRel, Ordering::Relaxed)
+                    .is_ok();
+                // Drop the reference resolve took.
+                self.decrement_ref_count(old);
+                if swapped {
+                    self.get_data(handle.index & SLOT_MASK)
+                        .version
+                        .fetch_add(1, Ordering::Release);
+                    // And the old value's link from the handle.
+                    self.decrement_ref_count(old);
+                    return Ok(());
+                }
+                // Lost to another replace or a free - try again against whatever is there now.
+            }
+        }
+    }
+
+    /// How many times the object has been replaced, or None if the handle is stale.  Readers can compare it to a value they saw earlier to spot a reload.
+    pub fn version(&self, handle: ResourceHandle<T>) -> Option<u32> {
+        let index = self.slot_of(handle)?;
+        let data = unsafe { self.get_data(index) };
+        let version = data.version.load(Ordering::Acquire);
+        if data.unique_id.load(Ordering::Acquire) != handle.unique {
+            return None;
+        }
+        return Some(version);
+    }
+
+    /// Whether the handle still refers to a stored object.  It may be freed by another thread straight after.
+    pub fn is_live(&self, handle: ResourceHandle<T>) -> bool {
+        let index = match self.slot_of(handle) {
+            Some(x) => x,
+            None => return false,
+        };
+        let data = unsafe { self.get_data(index) };
+        return data.unique_id.load(Ordering::Acquire) == handle.unique;
     }
 
     /// Release the local reference to the object stored at the handle location.  
     /// The object will not actually be dropped until all references are released, however no handles will return the object.
-    pub fn free(&self, handle: ResourceHandle) -> bool {
-        if handle.index == REF_NULL {
-            return false;
-        }
+    pub fn free(&self, handle: ResourceHandle<T>) -> bool {
+        let index = match self.slot_of(handle) {
+            Some(x) => x,
+            None => return false,
+        };
         unsafe {
             let next = (handle.unique & !INITIALIZED).wrapping_add(UNIQUE_OFFSET);
-            let data = self.get_data(handle.index);
+            let data = self.get_data(index);
             match data.unique_id.compare_exchange(
                 handle.unique,
                 next,
@@ -177,7 +640,10 @@ impl<'a, T> ResourceManager<'a, T> {
                 Ordering::Relaxed,
             ) {
                 Ok(_) => {
-                    self.decrement_ref_count(handle.index);
+                    // Whichever value is current loses its link, and the handle gives up the slot.
+                    let target = data.forward.swap(REF_NULL, Ordering::AcqRel);
+                    self.decrement_ref_count(target);
+                    self.release_slot(index);
                     return true;
                 }
 
@@ -216,20 +682,16 @@ impl<'a, T: Sync> ResourceManager<'a, T> {
         }
     }
     /// Get a reference counted reference to the object based on a handle.  Returns None if the handle points to empty space.
-    pub fn retain(&'a self, handle: ResourceHandle) -> Option<ResourceRef<'a, T>> {
-        if handle.index == REF_NULL {
-            return None;
-        }
+    pub fn retain(&'a self, handle: ResourceHandle<T>) -> Option<ResourceRef<'a, T>> {
+        let index = self.resolve(handle)?;
         unsafe {
-            self.increment_ref_count(handle.index);
-            let data = self.get_data(handle.index);
-            if data.unique_id.load(Ordering::Acquire) != handle.unique {
-                self.decrement_ref_count(handle.index);
+            if self.get_data(index).state.load(Ordering::Acquire) != READY {
+                self.decrement_ref_count(index);
                 // println!("none {} {}", index, unique);
                 return None;
             } else {
                 return Some(ResourceRef {
-                    index: NonZeroU32::new_unchecked(handle.index | NON_NULL_BIT),
+                    index: NonZeroU32::new_unchecked(index | NON_NULL_BIT),
                     _phantom: PhantomData,
                     _lifetime: PhantomData,
                 });
@@ -238,5 +700,125 @@ impl<'a, T: Sync> ResourceManager<'a, T> {
     }
 }
 
+impl<'a, T: Sync> ResourceManager<'a, T> {
+    /// Like retain, but the reference releases itself when dropped.
+    pub fn retain_guard(&'a self, handle: ResourceHandle<T>) -> Option<ResourceGuard<'a, T>> {
+        let resource = self.retain(handle)?;
+        return Some(self.guard(resource));
+    }
+
+    /// Wrap a reference from retain so it releases itself when dropped.
+    pub fn guard(&'a self, resource: ResourceRef<'a, T>) -> ResourceGuard<'a, T> {
+        return ResourceGuard {
+            manager: self,
+            index: resource.index.get() & REF_MASK,
+            _phantom: PhantomData,
+        };
+    }
+}
+
+impl<'a, T: Sync> ResourceManager<'a, T> {
+    /// Walk every live, Ready object, retaining each one as it is visited.  Safe to use while other threads store and free.
+    /// Objects stored or freed during the walk may or may not be seen.  Every yielded reference must be released.
+    pub fn iter(&'a self) -> ResourceIter<'a, T> {
+        return ResourceIter {
+            manager: self,
+            index: 0,
+        };
+    }
+}
+
+/// Iterator returned by ResourceManager::iter.  Yields a handle and a retained reference for each live object.
+pub struct ResourceIter<'a, T: Sync> {
+    manager: &'a ResourceManager<'a, T>,
+    index: u32,
+}
+
+impl<'a, T: Sync> Iterator for ResourceIter<'a, T> {
+    type Item = (ResourceHandle<T>, ResourceRef<'a, T>);
+    fn next(&mut self) -> Option<Self::Item> {
+        let manager = self.manager;
+        // Slots past the high water mark have never been used.
+        let end = core::cmp::min(
+            manager.high_water_mark.load(Ordering::Acquire),
+            manager.capacity,
+        );
+        while self.index < end {
+            let index = self.index;
+            self.index += 1;
+            // Only handle slots - not free slots, or ones holding replacement values.
+            let unique = unsafe { manager.get_data(index) }
+                .unique_id
+                .load(Ordering::Acquire);
+            if unique & INITIALIZED == 0 {
+                continue;
+            }
+            let handle = manager.make_handle(index, unique);
+            // Skips objects freed or replaced since, or not loaded yet.
+            if let Some(resource) = manager.retain(handle) {
+                return Some((handle, resource));
+            }
+        }
+        return None;
+    }
+}
+
+/// A reference counted reference that releases itself on drop, rather than having to be passed back to ResourceManager::release.
+/// Cloning it takes another reference.
+pub struct ResourceGuard<'a, T: Sync> {
+    manager: &'a ResourceManager<'a, T>,
+    index: u32,
+    _phantom: PhantomData<*mut u8>, //to disable send and sync, like ResourceRef
+}
+
+impl<'a, T: Sync> ResourceGuard<'a, T> {
+    /// Give up the guard for a plain ResourceRef, which must then be released manually.
+    pub fn into_ref(self) -> ResourceRef<'a, T> {
+        let resource = ResourceRef {
+            index: unsafe { NonZeroU32::new_unchecked(self.index | NON_NULL_BIT) },
+            _phantom: PhantomData,
+            _lifetime: PhantomData,
+        };
+        core::mem::forget(self);
+        return resource;
+    }
+}
+
+impl<'a, T: Sync> Deref for ResourceGuard<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe {
+            let data = self.manager.get_data(self.index);
+            return data.data.as_ptr().as_ref().unwrap();
+        }
+    }
+}
+
+impl<'a, T: Sync> Clone for ResourceGuard<'a, T> {
+    fn clone(&self) -> ResourceGuard<'a, T> {
+        unsafe {
+            self.manager.increment_ref_count(self.index);
+        }
+        return ResourceGuard {
+            manager: self.manager,
+            index: self.index,
+            _phantom: PhantomData,
+        };
+    }
+}
+
+impl<'a, T: Sync> Drop for ResourceGuard<'a, T> {
+    fn drop(&mut self) {
+        unsafe {
+            self.manager.decrement_ref_count(self.index);
+        }
+    }
+}
+
+mod locked;
+pub use locked::LockedResourceManager;
+pub use locked::ResourceReadGuard;
+pub use locked::ResourceWriteGuard;
+
 #[cfg(test)]
 mod test;
@@ -0,0 +1,207 @@
+use super::ResourceHandle;
+use super::ResourceIter;
+use super::ResourceManager;
+use super::ResourceRef;
+use super::ResourceState;
+use super::REF_MASK;
+use crate::sync::RawRWSpinLock;
+use crate::sync::Unique;
+use core::marker::PhantomData;
+use core::ops::Deref;
+use core::ops::DerefMut;
+
+/// A ResourceManager whose objects can be mutated.  Each object slot has its own reader-writer lock, and objects are only reachable through read and write guards -
+/// there is no plain `get`, so every access takes the lock.  Handles are checked exactly as in ResourceManager.
+/// The locks live in a buffer of their own, so managers that are never locked pay nothing for them.
+pub struct LockedResourceManager<'a, T> {
+    manager: ResourceManager<'a, T>,
+    locks: Unique<RawRWSpinLock>,
+    _lifetime: PhantomData<&'a RawRWSpinLock>,
+}
+
+impl<'a, T: Send + Sync> LockedResourceManager<'a, T> {
+    /// Take over a manager, so its objects can only be reached through the locks.
+    /// This method is a kludge to work around lack of stable const-generics, const unions, etc.
+    /// It is up to the caller to ensure that the pointer passed in is truly static, and is not mutated externally.
+    /// The lock buffer must hold one lock for each slot of the manager - its capacity - and be zeroed.
+    pub const unsafe fn from_static(
+        manager: ResourceManager<'a, T>,
+        locks: &'a *mut RawRWSpinLock,
+    ) -> LockedResourceManager<'a, T> {
+        return LockedResourceManager {
+            manager: manager,
+            locks: Unique::new(*locks),
+            _lifetime: PhantomData,
+        };
+    }
+
+    /// References do not record which manager they came from, so one from a larger manager must not index past the end of this one's buffers.
+    #[inline(always)]
+    fn get_slot(&self, resource: &ResourceRef<'a, T>) -> u32 {
+        let slot = resource.index.get() & REF_MASK;
+        assert!(
+            slot < self.manager.capacity,
+            "resource reference does not belong to this manager"
+        );
+        return slot;
+    }
+
+    #[inline(always)]
+    fn get_lock(&self, slot: u32) -> &RawRWSpinLock {
+        return unsafe { &*self.locks.as_ptr().add(slot as usize) };
+    }
+
+    #[inline(always)]
+    fn get_value(&self, slot: u32) -> *mut T {
+        return unsafe { self.manager.get_data(slot).data.as_mut_ptr() };
+    }
+
+    /// Shared access to the object, waiting for any writer to finish.  Panics if the reference is out of range for this manager.
+    pub fn read<'r>(&'r self, resource: &'r ResourceRef<'a, T>) -> ResourceReadGuard<'r, T> {
+        let slot = self.get_slot(resource);
+        let lock = self.get_lock(slot);
+        lock.lock_read();
+        return ResourceReadGuard {
+            lock: lock,
+            data: self.get_value(slot),
+            _lifetime: PhantomData,
+        };
+    }
+
+    /// Exclusive access to the object, waiting for readers to finish.  Pending writers keep new readers out.
+    /// Panics if the reference is out of range for this manager.
+    pub fn write<'r>(&'r self, resource: &'r ResourceRef<'a, T>) -> ResourceWriteGuard<'r, T> {
+        let slot = self.get_slot(resource);
+        let lock = self.get_lock(slot);
+        lock.lock_write();
+        return ResourceWriteGuard {
+            lock: lock,
+            data: self.get_value(slot),
+            _lifetime: PhantomData,
+        };
+    }
+
+    /// See ResourceManager::store.
+    pub fn store(&self, obj: T) -> ResourceHandle<T> {
+        return self.manager.store(obj);
+    }
+
+    /// See ResourceManager::reserve.
+    pub fn reserve(&self) -> ResourceHandle<T> {
+        return self.manager.reserve();
+    }
+
+    /// See ResourceManager::start_loading.
+    pub fn start_loading(&self, handle: ResourceHandle<T>) -> bool {
+        return self.manager.start_loading(handle);
+    }
+
+    /// See ResourceManager::complete.
+    pub fn complete(&self, handle: ResourceHandle<T>, value: T) -> Result<(), T> {
+        return self.manager.complete(handle, value);
+    }
+
+    /// See ResourceManager::fail.
+    pub fn fail(&self, handle: ResourceHandle<T>, error: u32) -> bool {
+        return self.manager.fail(handle, error);
+    }
+
+    /// See ResourceManager::state.
+    pub fn state(&self, handle: ResourceHandle<T>) -> Option<ResourceState> {
+        return self.manager.state(handle);
+    }
+
+    /// See ResourceManager::replace.  Writers holding the old value keep it - their changes do not carry over.
+    pub fn replace(&self, handle: ResourceHandle<T>, value: T) -> Result<(), T> {
+        return self.manager.replace(handle, value);
+    }
+
+    /// See ResourceManager::version.
+    pub fn version(&self, handle: ResourceHandle<T>) -> Option<u32> {
+        return self.manager.version(handle);
+    }
+
+    /// See ResourceManager::is_live.
+    pub fn is_live(&self, handle: ResourceHandle<T>) -> bool {
+        return self.manager.is_live(handle);
+    }
+
+    /// See ResourceManager::free.
+    pub fn free(&self, handle: ResourceHandle<T>) -> bool {
+        return self.manager.free(handle);
+    }
+
+    /// See ResourceManager::collect.
+    pub fn collect(&self, budget: usize) -> usize {
+        return self.manager.collect(budget);
+    }
+
+    /// See ResourceManager::retain.
+    pub fn retain(&'a self, handle: ResourceHandle<T>) -> Option<ResourceRef<'a, T>> {
+        return self.manager.retain(handle);
+    }
+
+    /// See ResourceManager::clone.
+    pub fn clone(&'a self, resource: &ResourceRef<'a, T>) -> ResourceRef<'a, T> {
+        return self.manager.clone(resource);
+    }
+
+    /// See ResourceManager::release.
+    pub fn release(&self, resource: ResourceRef<'a, T>) {
+        self.manager.release(resource);
+    }
+
+    /// See ResourceManager::iter.
+    pub fn iter(&'a self) -> ResourceIter<'a, T> {
+        return self.manager.iter();
+    }
+}
+
+/// Shared access to an object in a LockedResourceManager.  Other readers may hold the object at the same time.
+pub struct ResourceReadGuard<'r, T> {
+    lock: &'r RawRWSpinLock,
+    data: *mut T,
+    _lifetime: PhantomData<&'r T>,
+}
+
+impl<'r, T> Drop for ResourceReadGuard<'r, T> {
+    fn drop(&mut self) {
+        self.lock.unlock_read();
+    }
+}
+
+impl<'r, T> Deref for ResourceReadGuard<'r, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        return unsafe { &*self.data };
+    }
+}
+
+/// Exclusive access to an object in a LockedResourceManager.
+pub struct ResourceWriteGuard<'r, T> {
+    lock: &'r RawRWSpinLock,
+    data: *mut T,
+    _lifetime: PhantomData<&'r mut T>,
+}
+
+impl<'r, T> Drop for ResourceWriteGuard<'r, T> {
+    fn drop(&mut self) {
+        self.lock.unlock_write();
+    }
+}
+
+impl<'r, T> Deref for ResourceWriteGuard<'r, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        return unsafe { &*self.data };
+    }
+}
+
+impl<'r, T> DerefMut for ResourceWriteGuard<'r, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        return unsafe { &mut *self.data };
+    }
+}
+
+#[cfg(test)]
+mod test;
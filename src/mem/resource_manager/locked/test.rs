@@ -0,0 +1,96 @@
+use crate::mem::resource_manager::test::test::Buffers;
+use std::thread;
+
+struct Counter {
+    value: u64,
+    // Written alongside value, so a torn update would show as a mismatch.
+    copy: u64,
+}
+
+#[test]
+fn read_write() {
+    let buffers = Buffers::new(16);
+    let manager = buffers.locked_manager();
+
+    let handle = manager.store(Counter { value: 1, copy: 1 });
+    let r = manager.retain(handle).unwrap();
+    {
+        let a = manager.read(&r);
+        let b = manager.read(&r);
+        assert_eq!(a.value + b.value, 2);
+    }
+    {
+        let mut w = manager.write(&r);
+        w.value = 2;
+        w.copy = 2;
+    }
+    let r2 = manager.clone(&r);
+    assert_eq!(manager.read(&r2).value, 2);
+    manager.release(r2);
+
+    // Handles are still checked on retain.
+    assert!(manager.free(handle));
+    assert!(manager.retain(handle).is_none());
+    // The object is still there for the reference we hold.
+    assert_eq!(manager.read(&r).copy, 2);
+    manager.release(r);
+}
+
+#[test]
+fn threads() {
+    let buffers = Buffers::new(16);
+    let manager = buffers.locked_manager();
+    let handle = manager.store(Counter { value: 0, copy: 0 });
+
+    thread::scope(|s| {
+        for _ in 0..2 {
+            s.spawn(|| {
+                let r = manager.retain(handle).unwrap();
+                for _ in 0..1000 {
+                    {
+                        let mut w = manager.write(&r);
+                        w.value += 1;
+                        thread::yield_now();
+                        w.copy += 1;
+                    }
+                }
+                manager.release(r);
+            });
+        }
+        for _ in 0..2 {
+            s.spawn(|| {
+                let r = manager.retain(handle).unwrap();
+                for _ in 0..1000 {
+                    {
+                        let g = manager.read(&r);
+                        assert_eq!(g.value, g.copy);
+                    }
+                    thread::yield_now();
+                }
+                manager.release(r);
+            });
+        }
+    });
+
+    let r = manager.retain(handle).unwrap();
+    assert_eq!(manager.read(&r).value, 2000);
+    manager.release(r);
+    assert!(manager.free(handle));
+}
+
+#[test]
+#[should_panic(expected = "does not belong to this manager")]
+fn foreign_reference() {
+    let big_buffers = Buffers::new(16);
+    let small_buffers = Buffers::new(4);
+    let big = big_buffers.locked_manager();
+    let small = small_buffers.locked_manager();
+
+    let mut handle = big.store(Counter { value: 0, copy: 0 });
+    for i in 1..8 {
+        handle = big.store(Counter { value: i, copy: i });
+    }
+    // Slot 7 is past the end of the small manager's buffers.
+    let r = big.retain(handle).unwrap();
+    small.read(&r);
+}
@@ -1,13 +1,20 @@
 #[cfg(test)]
-mod test {
+pub(super) mod test {
+    use crate::mem::LockedResourceManager;
+    use crate::mem::Queue;
     use crate::mem::QUEUE_U32_NULL;
     // use crate::mem::resource_manager::Resource;
     use crate::mem::ResourceData;
     use crate::mem::ResourceHandle;
     use crate::mem::ResourceManager;
     use crate::mem::ResourceRef;
+    use crate::mem::ResourceState;
     use crate::sync::IndexSpinlock;
+    use crate::sync::RawRWSpinLock;
     use core::sync::atomic::AtomicU32;
+    use core::sync::atomic::AtomicU64;
+    use core::sync::atomic::AtomicUsize;
+    use core::sync::atomic::Ordering;
     use std::thread;
 
     static mut QUEUE_BUFFER: [u32; 1024] = [QUEUE_U32_NULL; 1024];
@@ -33,11 +40,119 @@ mod test {
         }
     }
 
+    /// Zeroed storage for a manager of `capacity` objects, which must outlive the managers made from it.
+    pub(crate) struct Buffers<T> {
+        capacity: u32,
+        _queue: Vec<u32>,
+        _destroy: Vec<u32>,
+        _data: Vec<ResourceData<T>>,
+        _locks: Vec<RawRWSpinLock>,
+        queue_ptr: *mut AtomicU32,
+        destroy_ptr: *mut AtomicU32,
+        data_ptr: *mut ResourceData<T>,
+        locks_ptr: *mut RawRWSpinLock,
+    }
+
+    impl<T> Buffers<T> {
+        pub(crate) fn new(capacity: u32) -> Buffers<T> {
+            let mut queue = vec![QUEUE_U32_NULL; capacity as usize];
+            let mut destroy = vec![QUEUE_U32_NULL; capacity as usize];
+            let mut data = Vec::with_capacity(capacity as usize);
+            let mut locks = Vec::with_capacity(capacity as usize);
+            unsafe {
+                core::ptr::write_bytes(data.as_mut_ptr(), 0, capacity as usize);
+                core::ptr::write_bytes(locks.as_mut_ptr(), 0, capacity as usize);
+            }
+            return Buffers {
+                capacity: capacity,
+                queue_ptr: queue.as_mut_ptr() as *mut AtomicU32,
+                destroy_ptr: destroy.as_mut_ptr() as *mut AtomicU32,
+                data_ptr: data.as_mut_ptr(),
+                locks_ptr: locks.as_mut_ptr(),
+                _queue: queue,
+                _destroy: destroy,
+                _data: data,
+                _locks: locks,
+            };
+        }
+
+        /// Only make one manager from each Buffers.
+        pub(crate) fn manager(&self) -> ResourceManager<'_, T> {
+            return self.manager_with_id(0);
+        }
+
+        pub(crate) fn manager_with_id(&self, id: u8) -> ResourceManager<'_, T> {
+            return unsafe {
+                ResourceManager::from_static_with_id(
+                    &self.queue_ptr,
+                    &self.data_ptr,
+                    self.capacity,
+                    id,
+                )
+            };
+        }
+
+        /// A manager whose objects are dropped by `collect`, see `from_static_deferred`.
+        pub(crate) fn deferred_manager(&self) -> ResourceManager<'_, T> {
+            return unsafe {
+                ResourceManager::from_static_deferred(
+                    &self.queue_ptr,
+                    &self.destroy_ptr,
+                    &self.data_ptr,
+                    self.capacity,
+                    0,
+                )
+            };
+        }
+
+        pub(crate) fn locked_manager(&self) -> LockedResourceManager<'_, T>
+        where
+            T: Send + Sync,
+        {
+            return unsafe { LockedResourceManager::from_static(self.manager(), &self.locks_ptr) };
+        }
+    }
+
+    /// Counts the drops of the Counted values it makes.  Each test has its own, so tests running in parallel do not see each other's drops.
+    pub(crate) struct DropCounter {
+        drops: AtomicUsize,
+    }
+
+    impl DropCounter {
+        pub(crate) fn new() -> DropCounter {
+            return DropCounter {
+                drops: AtomicUsize::new(0),
+            };
+        }
+
+        pub(crate) fn make(&self, data: u64) -> Counted<'_> {
+            return Counted {
+                data: data,
+                counter: self,
+            };
+        }
+
+        pub(crate) fn drops(&self) -> usize {
+            return self.drops.load(Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) struct Counted<'c> {
+        pub(crate) data: u64,
+        counter: &'c DropCounter,
+    }
+
+    impl<'c> Drop for Counted<'c> {
+        fn drop(&mut self) {
+            self.counter.drops.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
     #[test]
     fn init() {
         let  _l = LOCK.lock();
         for _k in 0..65535 {
-            let mut t: Vec<ResourceHandle> = Vec::new();
+            let mut t: Vec<ResourceHandle<Simple>> = Vec::new();
             for i in 0..16 {
                 t.push(MANAGER.store(Simple { data: i }));
             }
@@ -53,7 +168,7 @@ mod test {
     fn retain_clone_release() {
         let  _l = LOCK.lock();
         for _k in 0..65535 {
-            let mut t: Vec<ResourceHandle> = Vec::new();
+            let mut t: Vec<ResourceHandle<Simple>> = Vec::new();
             let mut q: Vec<ResourceRef<Simple>> = Vec::new();
             for i in 0..16 {
                 let tmp = MANAGER.store(Simple { data: i });
@@ -87,7 +202,7 @@ mod test {
             let mut children = vec![];
             children.push(thread::spawn(|| {
                 for _j in 0..256 {
-                    let mut t: Vec<ResourceHandle> = Vec::new();
+                    let mut t: Vec<ResourceHandle<Simple>> = Vec::new();
                     let mut q: Vec<ResourceRef<Simple>> = Vec::new();
                     for i in 0..256 {
                         let tmp = MANAGER.store(Simple { data: i });
@@ -120,4 +235,375 @@ mod test {
         }
         // l.write(12);
     }
+
+    #[test]
+    fn handle_queue() {
+        let _l = LOCK.lock();
+        unsafe {
+            let mut buffer_local: [u64; 16] = [u64::MAX; 16];
+            let buffer_ptr = &mut buffer_local[0] as *mut u64 as *mut AtomicU64;
+            let q = Queue::<ResourceHandle<Simple>>::from_static(&buffer_ptr, 16);
+            for i in 0..16 {
+                assert!(q.enqueue(MANAGER.store(Simple { data: i })));
+            }
+            for i in 0..16 {
+                let handle = q.dequeue().unwrap();
+                let r = MANAGER.retain(handle).unwrap();
+                assert_eq!(MANAGER.get(&r).data, i);
+                MANAGER.release(r);
+                assert!(MANAGER.free(handle));
+            }
+            assert_eq!(q.dequeue(), None);
+        }
+    }
+
+    #[test]
+    fn handle_queue_null() {
+        let mut buffer_local: [u64; 4] = [u64::MAX; 4];
+        let buffer_ptr = &mut buffer_local[0] as *mut u64 as *mut AtomicU64;
+        let q = unsafe { Queue::<ResourceHandle<Simple>>::from_static(&buffer_ptr, 4) };
+        // The null handle does not convert to the sentinel, so it survives a trip through the queue.
+        assert!(q.enqueue(ResourceHandle::null()));
+        assert!(q.dequeue().unwrap().is_null());
+    }
+
+    #[test]
+    fn guard() {
+        let counter = DropCounter::new();
+        let buffers = Buffers::new(16);
+        let manager = buffers.manager();
+
+        let handle = manager.store(counter.make(7));
+        {
+            let a = manager.retain_guard(handle).unwrap();
+            let b = a.clone();
+            assert_eq!(a.data, 7);
+            assert_eq!(b.data, 7);
+            // Freeing the handle leaves the object alive while guards hold it.
+            assert!(manager.free(handle));
+            assert!(manager.retain_guard(handle).is_none());
+            drop(a);
+            assert_eq!(counter.drops(), 0);
+            assert_eq!(b.data, 7);
+        }
+        assert_eq!(counter.drops(), 1);
+
+        // Guards and manual references interoperate.
+        let handle = manager.store(counter.make(8));
+        let r = manager.retain(handle).unwrap();
+        let g = manager.guard(r);
+        let r = g.clone().into_ref();
+        drop(g);
+        assert_eq!(manager.get(&r).data, 8);
+        assert!(manager.free(handle));
+        assert_eq!(counter.drops(), 1);
+        manager.release(r);
+        assert_eq!(counter.drops(), 2);
+    }
+
+    #[test]
+    fn manager_scope() {
+        let buffers_a = Buffers::new(16);
+        let buffers_b = Buffers::new(16);
+        let a = buffers_a.manager_with_id(1);
+        let b = buffers_b.manager_with_id(2);
+
+        // Both handles use slot 0 with the same unique, so only the manager id tells them apart.
+        let handle_a = a.store(Simple { data: 1 });
+        let handle_b = b.store(Simple { data: 2 });
+        assert_eq!(handle_a.manager_id(), 1);
+        assert_eq!(handle_b.manager_id(), 2);
+        assert!(handle_a != handle_b);
+
+        assert!(b.retain(handle_a).is_none());
+        assert!(a.retain_guard(handle_b).is_none());
+        assert!(!b.free(handle_a));
+
+        let r = b.retain(handle_b).unwrap();
+        assert_eq!(b.get(&r).data, 2);
+        b.release(r);
+        assert!(a.free(handle_a));
+        assert!(b.free(handle_b));
+    }
+
+    #[test]
+    fn iterate() {
+        let buffers = Buffers::new(16);
+        let manager = buffers.manager();
+        assert!(manager.iter().next().is_none());
+
+        let mut t: Vec<ResourceHandle<Simple>> = Vec::new();
+        for i in 0..8 {
+            t.push(manager.store(Simple { data: i }));
+        }
+        // A freed object that is still referenced must not be visited.
+        let held = manager.retain(t[1]).unwrap();
+        assert!(manager.free(t[1]));
+        assert!(manager.free(t[4]));
+        // Retaining a stale handle to an empty slot must not disturb it.
+        assert!(manager.retain(t[4]).is_none());
+
+        let mut seen = Vec::new();
+        for (handle, r) in manager.iter() {
+            assert_eq!(t[manager.get(&r).data as usize], handle);
+            seen.push(manager.get(&r).data);
+            manager.release(r);
+        }
+        assert_eq!(seen, vec![0, 2, 3, 5, 6, 7]);
+        manager.release(held);
+
+        // The iterator's handles are as good as the originals.
+        let (handle, r) = manager.iter().next().unwrap();
+        manager.release(r);
+        assert!(manager.free(handle));
+        assert!(manager.retain(t[0]).is_none());
+        let mut count = 0;
+        for (_, r) in manager.iter() {
+            manager.release(r);
+            count += 1;
+        }
+        assert_eq!(count, 5);
+    }
+
+    #[test]
+    fn lifecycle() {
+        let counter = DropCounter::new();
+        let buffers = Buffers::new(16);
+        let manager = buffers.manager();
+
+        let handle = manager.reserve();
+        assert_eq!(manager.state(handle), Some(ResourceState::Reserved));
+        assert!(manager.retain(handle).is_none());
+        assert!(manager.start_loading(handle));
+        assert!(!manager.start_loading(handle));
+        assert_eq!(manager.state(handle), Some(ResourceState::Loading));
+        assert!(manager.complete(handle, counter.make(5)).is_ok());
+        assert_eq!(manager.state(handle), Some(ResourceState::Ready));
+        // Only one result is accepted.
+        let rejected = manager.complete(handle, counter.make(6));
+        assert_eq!(rejected.unwrap_err().data, 6);
+        assert!(!(manager.fail(handle, 1)));
+        let r = manager.retain(handle).unwrap();
+        assert_eq!(manager.get(&r).data, 5);
+        manager.release(r);
+        // Only the rejected value has been dropped - the handle still holds the object.
+        assert_eq!(counter.drops(), 1);
+
+        // Failed and reserved slots have no object to drop when freed.
+        let failed = manager.reserve();
+        assert!(manager.fail(failed, 404));
+        assert_eq!(manager.state(failed), Some(ResourceState::Failed(404)));
+        assert!(manager.retain(failed).is_none());
+        assert!(manager.complete(failed, counter.make(7)).is_err());
+        let reserved = manager.reserve();
+        assert!(manager.free(failed));
+        assert!(manager.free(reserved));
+        assert_eq!(manager.state(failed), None);
+        assert_eq!(counter.drops(), 2);
+
+        // Completing after the handle was freed gives the value back.
+        let late = manager.reserve();
+        assert!(manager.free(late));
+        assert!(manager.complete(late, counter.make(8)).is_err());
+        assert_eq!(counter.drops(), 3);
+
+        assert!(manager.free(handle));
+        assert_eq!(counter.drops(), 4);
+
+        // Slots recycled from reserve work as normal.
+        let mut t = Vec::new();
+        for i in 0..16 {
+            t.push(manager.store(counter.make(i)));
+        }
+        let mut count = 0;
+        for (handle, r) in manager.iter() {
+            assert_eq!(manager.state(handle), Some(ResourceState::Ready));
+            manager.release(r);
+            count += 1;
+        }
+        assert_eq!(count, 16);
+        for handle in t {
+            assert!(manager.free(handle));
+        }
+    }
+
+    #[test]
+    fn background_load() {
+        let buffers = Buffers::new(16);
+        let manager = buffers.manager();
+
+        let handles: Vec<ResourceHandle<Simple>> = (0..8).map(|_| manager.reserve()).collect();
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                for (i, handle) in handles.iter().enumerate() {
+                    assert!(manager.start_loading(*handle));
+                    std::thread::yield_now();
+                    if i % 2 == 0 {
+                        let value = Simple { data: i as u64 };
+                        assert!(manager.complete(*handle, value).is_ok());
+                    } else {
+                        assert!(manager.fail(*handle, i as u32));
+                    }
+                }
+            });
+            for (i, handle) in handles.iter().enumerate() {
+                loop {
+                    match manager.state(*handle).unwrap() {
+                        ResourceState::Ready => {
+                            let r = manager.retain(*handle).unwrap();
+                            assert_eq!(manager.get(&r).data, i as u64);
+                            manager.release(r);
+                            break;
+                        }
+                        ResourceState::Failed(x) => {
+                            assert_eq!(x, i as u32);
+                            break;
+                        }
+                        _ => std::thread::yield_now(),
+                    }
+                }
+            }
+        });
+        for handle in handles {
+            assert!(manager.free(handle));
+        }
+    }
+
+    #[test]
+    fn replace() {
+        let counter = DropCounter::new();
+        let buffers = Buffers::new(4);
+        let manager = buffers.manager();
+
+        let handle = manager.store(counter.make(1));
+        assert_eq!(manager.version(handle), Some(0));
+        let old = manager.retain(handle).unwrap();
+        assert!(manager.replace(handle, counter.make(2)).is_ok());
+        assert_eq!(manager.version(handle), Some(1));
+        assert_eq!(manager.state(handle), Some(ResourceState::Ready));
+        let new = manager.retain(handle).unwrap();
+        assert_eq!(manager.get(&old).data, 1);
+        assert_eq!(manager.get(&new).data, 2);
+        // The old value lives until its last reference goes.
+        assert_eq!(counter.drops(), 0);
+        manager.release(old);
+        assert_eq!(counter.drops(), 1);
+
+        assert!(manager.replace(handle, counter.make(3)).is_ok());
+        assert_eq!(manager.version(handle), Some(2));
+        let (_, r) = manager.iter().next().unwrap();
+        assert_eq!(manager.get(&r).data, 3);
+        manager.release(r);
+        assert_eq!(manager.get(&new).data, 2);
+        manager.release(new);
+        assert_eq!(counter.drops(), 2);
+
+        // Reserved objects cannot be replaced - they are completed instead.
+        let reserved = manager.reserve();
+        let rejected = manager.replace(reserved, counter.make(4));
+        assert_eq!(rejected.unwrap_err().data, 4);
+        assert!(manager.free(reserved));
+
+        assert!(manager.free(handle));
+        assert_eq!(manager.version(handle), None);
+        assert!(manager.replace(handle, counter.make(5)).is_err());
+        assert_eq!(counter.drops(), 5);
+
+        // Every slot came back.
+        let mut t = Vec::new();
+        for i in 0..4 {
+            let handle = manager.store(counter.make(i));
+            assert!(!handle.is_null());
+            t.push(handle);
+        }
+        for handle in t {
+            assert!(manager.free(handle));
+        }
+    }
+
+    #[test]
+    fn replace_threads() {
+        let counter = DropCounter::new();
+        let buffers = Buffers::new(64);
+        let manager = buffers.manager();
+        let handle = manager.store(counter.make(0));
+        let mut replaced = 0;
+
+        std::thread::scope(|s| {
+            for _ in 0..3 {
+                s.spawn(|| {
+                    let mut last = 0;
+                    let mut last_version = 0;
+                    for _ in 0..2000 {
+                        let r = manager.retain(handle).unwrap();
+                        // Values and versions only move forward.
+                        let data = manager.get(&r).data;
+                        assert!(data >= last);
+                        last = data;
+                        let version = manager.version(handle).unwrap();
+                        assert!(version >= last_version);
+                        last_version = version;
+                        manager.release(r);
+                        std::thread::yield_now();
+                    }
+                });
+            }
+            for i in 1..500 {
+                // Slots may all be held by readers for a moment.
+                if manager.replace(handle, counter.make(i)).is_ok() {
+                    replaced += 1;
+                }
+                std::thread::yield_now();
+            }
+        });
+        assert!(manager.free(handle));
+        // Every value was dropped - the first, each replacement, and each rejected one.
+        assert_eq!(counter.drops(), 500);
+        assert_eq!(manager.version(handle), None);
+        assert!(replaced > 0);
+    }
+
+    #[test]
+    fn deferred_destruction() {
+        let counter = DropCounter::new();
+        let buffers = Buffers::new(4);
+        let manager = buffers.deferred_manager();
+        assert_eq!(manager.collect(8), 0);
+
+        let mut t = Vec::new();
+        for i in 0..4 {
+            t.push(manager.store(counter.make(i)));
+        }
+        // The last references go on another thread, which must not drop anything.
+        thread::scope(|s| {
+            s.spawn(|| {
+                let r = manager.retain(t[0]).unwrap();
+                for handle in t.iter() {
+                    assert!(manager.free(*handle));
+                }
+                assert_eq!(manager.get(&r).data, 0);
+                manager.release(r);
+            });
+        });
+        assert_eq!(counter.drops(), 0);
+        // Slots stay taken until their objects are collected.
+        assert!(manager.store(counter.make(4)).is_null());
+        assert_eq!(counter.drops(), 1);
+
+        assert_eq!(manager.collect(3), 3);
+        assert_eq!(counter.drops(), 4);
+        assert_eq!(manager.collect(3), 1);
+        assert_eq!(manager.collect(3), 0);
+        assert_eq!(counter.drops(), 5);
+
+        // Collected slots are reused, and failed loads never reach the queue.
+        let handle = manager.store(counter.make(5));
+        let failed = manager.reserve();
+        assert!(manager.fail(failed, 1));
+        assert!(manager.free(failed));
+        assert_eq!(manager.collect(8), 0);
+        assert!(manager.free(handle));
+        assert_eq!(manager.collect(8), 1);
+        assert_eq!(counter.drops(), 6);
+    }
 }
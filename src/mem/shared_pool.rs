@@ -0,0 +1,245 @@
+use crate::mem::mmap;
+use crate::mem::Queue;
+use core::ffi::CStr;
+use core::mem::size_of;
+use core::num::NonZeroUsize;
+use core::ptr;
+use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::Ordering;
+
+const SHARED_MAGIC: u64 = 0x4c4f_4f50_4853_4931; // "1ISHPOOL"
+
+/// Lives at the start of the shared region.  Everything here is visible to, and mutated by, every attached process.
+/// Nothing in it is an address - each process maps the region wherever it can, so only offsets from the start of the region are shared.
+#[repr(C)]
+struct SharedPoolHeader {
+    magic: u64,
+    size: usize,
+    block_size: usize,
+    capacity: usize,
+    blocks_offset: usize,
+    high_water_mark: AtomicUsize,
+    // A queue of free block offsets, over a buffer that follows the header.  It finds the buffer by offset, so it works from every mapping.
+    free: Queue<'static, NonZeroUsize>,
+}
+
+/// A fixed block size memory pool placed entirely inside a shared memory region, so several processes can allocate and free blocks in it.
+/// The header, the free list, and the blocks themselves all live in the region.
+/// Blocks are identified by their offset from the start of the region, which is never zero - the free list stores offsets, and offsets are what should be exchanged between processes.
+/// The region holds no addresses, so each process may map it at a different address.
+pub struct SharedMemoryPool {
+    region: mmap::MapAlloc,
+    fd: libc::c_int,
+}
+
+impl SharedMemoryPool {
+    /// The offset of the first block and the size of the region, or None if they overflow.
+    fn layout(block_size: usize, capacity: usize) -> Option<(usize, usize)> {
+        let queue_size = capacity.checked_mul(size_of::<AtomicUsize>())?;
+        let blocks_offset =
+            SharedMemoryPool::page_align(size_of::<SharedPoolHeader>().checked_add(queue_size)?)?;
+        let blocks_size = capacity.checked_mul(block_size)?;
+        let size = SharedMemoryPool::page_align(blocks_offset.checked_add(blocks_size)?)?;
+        return Some((blocks_offset, size));
+    }
+
+    fn page_align(size: usize) -> Option<usize> {
+        size.checked_add(mmap::page_size())?;
+        return Some(mmap::get_page_aligned_size(size));
+    }
+
+    /// The free list is indexed with 31 bits, like Queue.
+    fn valid_parameters(block_size: usize, capacity: usize) -> bool {
+        return block_size != 0 && capacity.is_power_of_two() && capacity <= 1 << 31;
+    }
+
+    /// Create a pool in a new named shared memory object, which other processes can `attach` to by name.
+    /// Capacity is the maximum number of blocks, and must be a non-zero power of two.  Returns None if the name already exists.
+    pub fn create(name: &CStr, block_size: usize, capacity: usize) -> Option<SharedMemoryPool> {
+        if !SharedMemoryPool::valid_parameters(block_size, capacity) {
+            return None;
+        }
+        let (blocks_offset, size) = SharedMemoryPool::layout(block_size, capacity)?;
+        let fd = mmap::shared_memory_create(name, size);
+        if fd < 0 {
+            return None;
+        }
+        let pool =
+            unsafe { SharedMemoryPool::initialize(fd, block_size, capacity, blocks_offset, size) };
+        if pool.is_none() {
+            mmap::shared_memory_unlink(name);
+        }
+        return pool;
+    }
+
+    /// Create a pool in an anonymous `memfd_create` region.  It can be shared with child processes through fork.
+    pub fn create_anonymous(
+        name: &CStr,
+        block_size: usize,
+        capacity: usize,
+    ) -> Option<SharedMemoryPool> {
+        if !SharedMemoryPool::valid_parameters(block_size, capacity) {
+            return None;
+        }
+        let (blocks_offset, size) = SharedMemoryPool::layout(block_size, capacity)?;
+        let fd = mmap::shared_memory_anonymous(name, size);
+        if fd < 0 {
+            return None;
+        }
+        return unsafe {
+            SharedMemoryPool::initialize(fd, block_size, capacity, blocks_offset, size)
+        };
+    }
+
+    /// Attach to a pool created with `create`, from this or any other process.
+    /// Returns None if the name does not exist, or does not hold a pool.
+    pub fn attach(name: &CStr) -> Option<SharedMemoryPool> {
+        let fd = mmap::shared_memory_open(name);
+        if fd < 0 {
+            return None;
+        }
+        unsafe {
+            let size = mmap::file_size(fd);
+            if size < size_of::<SharedPoolHeader>() {
+                libc::close(fd);
+                return None;
+            }
+            let region = mmap::map_shared(fd, size);
+            if region.is_null() {
+                libc::close(fd);
+                return None;
+            }
+            let pool = SharedMemoryPool {
+                region: region,
+                fd: fd,
+            };
+            let header = pool.header();
+            if header.magic != SHARED_MAGIC
+                || header.size != size
+                || !SharedMemoryPool::valid_parameters(header.block_size, header.capacity)
+                || SharedMemoryPool::layout(header.block_size, header.capacity)
+                    != Some((header.blocks_offset, size))
+            {
+                return None;
+            }
+            return Some(pool);
+        }
+    }
+
+    /// Remove the name of a pool created with `create`.  Attached processes keep working until they drop the pool.
+    pub fn unlink(name: &CStr) -> bool {
+        return mmap::shared_memory_unlink(name);
+    }
+
+    unsafe fn initialize(
+        fd: libc::c_int,
+        block_size: usize,
+        capacity: usize,
+        blocks_offset: usize,
+        size: usize,
+    ) -> Option<SharedMemoryPool> {
+        let region = mmap::map_shared(fd, size);
+        if region.is_null() {
+            libc::close(fd);
+            return None;
+        }
+        let header = region.memory as *mut SharedPoolHeader;
+        ptr::write(ptr::addr_of_mut!((*header).magic), SHARED_MAGIC);
+        ptr::write(ptr::addr_of_mut!((*header).size), size);
+        ptr::write(ptr::addr_of_mut!((*header).block_size), block_size);
+        ptr::write(ptr::addr_of_mut!((*header).capacity), capacity);
+        ptr::write(ptr::addr_of_mut!((*header).blocks_offset), blocks_offset);
+        ptr::write(
+            ptr::addr_of_mut!((*header).high_water_mark),
+            AtomicUsize::new(0),
+        );
+        // A freshly sized shared memory object is zero filled, which is an empty queue of NonZeroUsize.
+        Queue::initialize_relative(
+            ptr::addr_of_mut!((*header).free),
+            region.get_unchecked(size_of::<SharedPoolHeader>() as isize) as *mut AtomicUsize,
+            capacity,
+        );
+        return Some(SharedMemoryPool {
+            region: region,
+            fd: fd,
+        });
+    }
+
+    #[inline(always)]
+    fn header(&self) -> &SharedPoolHeader {
+        return unsafe { &*(self.region.memory as *const SharedPoolHeader) };
+    }
+
+    /// Allocate a block, returning its offset.  Returns None if every block is in use.
+    pub fn allocate(&self) -> Option<NonZeroUsize> {
+        let header = self.header();
+        let result = header.free.dequeue();
+        if result.is_some() {
+            return result;
+        }
+        // Same overflow handling as ResourceManager::store.
+        let next = header.high_water_mark.fetch_add(1, Ordering::Relaxed);
+        if next >= header.capacity {
+            header
+                .high_water_mark
+                .store(header.capacity, Ordering::Relaxed);
+            return None;
+        }
+        return NonZeroUsize::new(header.blocks_offset + next * header.block_size);
+    }
+
+    /// Return a block to the pool, from any attached process.
+    /// This is unsafe, because if you pass back a bad offset there is no checking.
+    #[inline(always)]
+    pub unsafe fn deallocate(&self, offset: NonZeroUsize) {
+        // There is a slot for every block, so the queue only fills if a block is freed twice.
+        let queued = self.header().free.enqueue(offset);
+        debug_assert!(queued);
+    }
+
+    /// The address of a block in this process.
+    #[inline(always)]
+    pub unsafe fn pointer_at(&self, offset: NonZeroUsize) -> *mut u8 {
+        return self.region.get_unchecked(offset.get() as isize);
+    }
+
+    /// The offset of a block, given its address in this process.  Returns None if the address is not inside this mapping of the pool.
+    #[inline(always)]
+    pub fn offset_of(&self, ptr: *const u8) -> Option<NonZeroUsize> {
+        let offset = (ptr as usize).checked_sub(self.region.memory as usize)?;
+        if offset >= self.region.size {
+            return None;
+        }
+        return NonZeroUsize::new(offset);
+    }
+
+    /// Size of each block in bytes.
+    pub fn block_size(&self) -> usize {
+        return self.header().block_size;
+    }
+
+    /// Maximum number of blocks.
+    pub fn capacity(&self) -> usize {
+        return self.header().capacity;
+    }
+
+    /// How many blocks have ever been handed out, across all processes.
+    pub fn high_water_mark(&self) -> usize {
+        return self.header().high_water_mark.load(Ordering::Relaxed);
+    }
+}
+
+unsafe impl Send for SharedMemoryPool {}
+unsafe impl Sync for SharedMemoryPool {}
+
+impl Drop for SharedMemoryPool {
+    fn drop(&mut self) {
+        unsafe {
+            mmap::free_page_aligned(self.region.memory, self.region.size);
+            libc::close(self.fd);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,169 @@
+use crate::mem::SharedMemoryPool;
+use core::num::NonZeroUsize;
+use std::ffi::CString;
+
+const PARENT_TAG: u64 = 0x1111;
+const CHILD_TAG: u64 = 0x2222;
+
+fn shm_name(name: &str) -> CString {
+    return CString::new(format!("/ico_memory_{}_{}", std::process::id(), name)).unwrap();
+}
+
+/// Allocate `count` blocks, tagging each with its own offset so overlapping allocations are detected.
+unsafe fn allocate_tagged(pool: &SharedMemoryPool, tag: u64, count: usize) -> Vec<NonZeroUsize> {
+    let mut result = Vec::with_capacity(count);
+    for _i in 0..count {
+        let offset = match pool.allocate() {
+            Some(x) => x,
+            None => break,
+        };
+        let block = pool.pointer_at(offset) as *mut u64;
+        *block = tag;
+        *block.offset(1) = offset.get() as u64;
+        result.push(offset);
+    }
+    return result;
+}
+
+#[test]
+fn alloc_free() {
+    let name = shm_name("alloc_free");
+    let pool = SharedMemoryPool::create_anonymous(&name, 64, 1024).unwrap();
+    unsafe {
+        let blocks = allocate_tagged(&pool, PARENT_TAG, 2048);
+        assert_eq!(blocks.len(), 1024);
+        assert_eq!(pool.allocate(), None);
+        for b in blocks.iter() {
+            assert_eq!(pool.offset_of(pool.pointer_at(*b)), Some(*b));
+            pool.deallocate(*b);
+        }
+        assert_eq!(allocate_tagged(&pool, PARENT_TAG, 2048).len(), 1024);
+    }
+    assert_eq!(pool.offset_of(core::ptr::null()), None);
+    // The region size would overflow.
+    assert!(SharedMemoryPool::create_anonymous(&name, usize::MAX / 4, 1 << 31).is_none());
+}
+
+#[test]
+fn create_attach() {
+    let name = shm_name("create_attach");
+    let pool = SharedMemoryPool::create(&name, 128, 256).unwrap();
+    assert!(SharedMemoryPool::create(&name, 128, 256).is_none());
+    unsafe {
+        // A second mapping in the same process lands at a different address, and sees the same blocks by offset.
+        let other = SharedMemoryPool::attach(&name).unwrap();
+        assert_eq!(other.block_size(), 128);
+        assert_eq!(other.capacity(), 256);
+        let blocks = allocate_tagged(&pool, PARENT_TAG, 16);
+        assert_eq!(blocks.len(), 16);
+        for b in blocks.iter() {
+            let block = other.pointer_at(*b) as *const u64;
+            assert!(block != pool.pointer_at(*b) as *const u64);
+            assert_eq!(*block, PARENT_TAG);
+            assert_eq!(*block.offset(1), b.get() as u64);
+            other.deallocate(*b);
+        }
+        assert_eq!(allocate_tagged(&pool, PARENT_TAG, 16), blocks);
+        assert_eq!(other.high_water_mark(), 16);
+    }
+    assert!(SharedMemoryPool::unlink(&name));
+    assert!(SharedMemoryPool::attach(&name).is_none());
+    assert_eq!(pool.block_size(), 128);
+    assert_eq!(pool.capacity(), 256);
+}
+
+#[test]
+fn attach_by_name() {
+    let name = shm_name("attach_by_name");
+    let pool = SharedMemoryPool::create(&name, 64, 1024).unwrap();
+    unsafe {
+        // The first block handed out is the lowest, which lets us walk them all afterwards.
+        let first = allocate_tagged(&pool, PARENT_TAG, 1)[0];
+        let base = first.get();
+        let pid = libc::fork();
+        assert_ne!(pid, -1);
+        if pid == 0 {
+            // Child - ignore the inherited mapping, and find the pool by name instead.
+            let other = match SharedMemoryPool::attach(&name) {
+                Some(x) => x,
+                None => libc::_exit(1),
+            };
+            if allocate_tagged(&other, CHILD_TAG, 256).len() != 256 {
+                libc::_exit(2);
+            }
+            libc::_exit(0);
+        }
+
+        let blocks = allocate_tagged(&pool, PARENT_TAG, 256);
+        assert_eq!(blocks.len(), 256);
+
+        let mut status = 0;
+        assert_eq!(libc::waitpid(pid, &mut status, 0), pid);
+        assert!(libc::WIFEXITED(status));
+        assert_eq!(libc::WEXITSTATUS(status), 0);
+        assert!(SharedMemoryPool::unlink(&name));
+
+        // Every block handed out went to exactly one process, and the child's tags are visible here.
+        assert_eq!(pool.high_water_mark(), 513);
+        let mut parent_blocks = 0;
+        let mut child_blocks = 0;
+        for i in 0..513 {
+            let offset = NonZeroUsize::new(base + i * 64).unwrap();
+            let block = pool.pointer_at(offset) as *const u64;
+            assert_eq!(*block.offset(1), offset.get() as u64);
+            match *block {
+                PARENT_TAG => parent_blocks += 1,
+                CHILD_TAG => child_blocks += 1,
+                _ => panic!("block at {} was never handed out", offset.get()),
+            }
+        }
+        assert_eq!(parent_blocks, 257);
+        assert_eq!(child_blocks, 256);
+    }
+}
+
+#[test]
+fn fork() {
+    let name = shm_name("fork");
+    let pool = SharedMemoryPool::create_anonymous(&name, 64, 4096).unwrap();
+    unsafe {
+        let pid = libc::fork();
+        assert_ne!(pid, -1);
+        if pid == 0 {
+            // Child - allocate concurrently with the parent, free half, and exit without unwinding.
+            let blocks = allocate_tagged(&pool, CHILD_TAG, 1024);
+            if blocks.len() != 1024 {
+                libc::_exit(1);
+            }
+            for b in blocks.iter().step_by(2) {
+                pool.deallocate(*b);
+            }
+            libc::_exit(0);
+        }
+
+        let blocks = allocate_tagged(&pool, PARENT_TAG, 1024);
+        assert_eq!(blocks.len(), 1024);
+
+        let mut status = 0;
+        assert_eq!(libc::waitpid(pid, &mut status, 0), pid);
+        assert!(libc::WIFEXITED(status));
+        assert_eq!(libc::WEXITSTATUS(status), 0);
+
+        // Nothing the child did overlapped our blocks.
+        for b in blocks.iter() {
+            let block = pool.pointer_at(*b) as *const u64;
+            assert_eq!(*block, PARENT_TAG);
+            assert_eq!(*block.offset(1), b.get() as u64);
+        }
+
+        // The parent may already have picked up some of the blocks the child freed.  The rest are reused before the pool grows.
+        let high_water_mark = pool.high_water_mark();
+        assert!(high_water_mark <= 2048);
+        let free = high_water_mark - 1024 - 512;
+        let reused = allocate_tagged(&pool, PARENT_TAG, free);
+        assert_eq!(reused.len(), free);
+        assert_eq!(pool.high_water_mark(), high_water_mark);
+        assert_eq!(allocate_tagged(&pool, PARENT_TAG, 1).len(), 1);
+        assert_eq!(pool.high_water_mark(), high_water_mark + 1);
+    }
+}
@@ -0,0 +1,39 @@
+use core::ptr;
+use core::sync::atomic::AtomicU32;
+use core::time::Duration;
+
+/// Put the calling thread to sleep while `atomic` still holds `expected`, or until `timeout` elapses.
+/// Returns false if the wait timed out.  Spurious wakeups are possible, so callers must re-check their condition.
+/// These are not the process private variants, so waiting on memory shared between processes also works.
+pub fn futex_wait(atomic: &AtomicU32, expected: u32, timeout: Option<Duration>) -> bool {
+    let timespec = timeout.map(|t| libc::timespec {
+        tv_sec: t.as_secs() as libc::time_t,
+        tv_nsec: t.subsec_nanos() as libc::c_long,
+    });
+    let timespec_ptr = match timespec {
+        Some(ref t) => t as *const libc::timespec,
+        None => ptr::null(),
+    };
+    let result = unsafe {
+        libc::syscall(
+            libc::SYS_futex,
+            atomic as *const AtomicU32,
+            libc::FUTEX_WAIT,
+            expected,
+            timespec_ptr,
+        )
+    };
+    return result == 0 || unsafe { *libc::__errno_location() } != libc::ETIMEDOUT;
+}
+
+/// Wake up to `count` threads waiting on `atomic`.
+pub fn futex_wake(atomic: &AtomicU32, count: i32) {
+    unsafe {
+        libc::syscall(
+            libc::SYS_futex,
+            atomic as *const AtomicU32,
+            libc::FUTEX_WAKE,
+            count,
+        );
+    }
+}
@@ -4,6 +4,17 @@ use core::ops::DerefMut;
 use core::sync::atomic::AtomicU32;
 use core::sync::atomic::Ordering;
 
+/// Call once per failed attempt in a spin loop.  Once the count passes limit it yields, so waiters do not burn whole time slices while the holder is descheduled.
+#[cfg(any(test, feature = "std"))]
+#[inline(always)]
+pub(crate) fn increment_yield_counter(value: u32, limit: u32) -> u32 {
+    if value > limit {
+        std::thread::yield_now();
+        return 0;
+    }
+    return value + 1;
+}
+
 /// Holds up 2^31 values
 #[repr(C)]
 #[derive(Debug)]
@@ -24,16 +35,6 @@ impl<T> Spinlock<T> {
         };
     }
 
-    #[cfg(any(test, feature = "std"))]
-    #[inline(always)]
-    fn increment_yield_counter(value: u32) -> u32 {
-        if value > 2 {
-            std::thread::yield_now();
-            return 0;
-        }
-        return value + 1;
-    }
-
     #[inline(always)]
     pub fn lock(&self) -> SpinlockGuard<T> {
         #[cfg(any(test, feature = "std"))]
@@ -59,7 +60,7 @@ impl<T> Spinlock<T> {
             } else {
                 #[cfg(any(test, feature = "std"))]
                 {
-                    counter = Spinlock::<T>::increment_yield_counter(counter);
+                    counter = increment_yield_counter(counter, 2);
                 }
 
                 lock_value = self.lock.load(Ordering::Acquire);
@@ -147,16 +148,6 @@ impl IndexSpinlock {
         };
     }
 
-    #[cfg(any(test, feature = "std"))]
-    #[inline(always)]
-    fn increment_yield_counter(value: u32) -> u32 {
-        if value > 1 {
-            std::thread::yield_now();
-            return 0;
-        }
-        return value + 1;
-    }
-
     #[inline(always)]
     pub fn try_lock(&self) -> Option<IndexSpinlockGuard> {
         let lock_value = self.lock.load(Ordering::Acquire);
@@ -207,7 +198,7 @@ impl IndexSpinlock {
             } else {
                 #[cfg(any(test, feature = "std"))]
                 {
-                    counter = IndexSpinlock::increment_yield_counter(counter);
+                    counter = increment_yield_counter(counter, 1);
                 }
 
                 lock_value = self.lock.load(Ordering::Acquire);
@@ -223,6 +214,12 @@ impl IndexSpinlock {
         return self.lock.load(Ordering::Relaxed) & IndexSpinlock::MASK;
     }
 
+    /// Read the value without locking.  Another thread may hold the lock and be about to change it, so this is only a snapshot.
+    #[inline(always)]
+    pub fn peek(&self) -> u32 {
+        return self.lock.load(Ordering::Acquire) & IndexSpinlock::MASK;
+    }
+
     /// Since this call borrows the Lock mutably, no actual locking needs to take place --
     /// the mutable borrow statically guarantees no locks exist.
     #[inline(always)]
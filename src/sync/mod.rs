@@ -1,12 +1,21 @@
+#[cfg(all(any(test, feature = "std"), target_os = "linux"))]
+mod futex;
 mod index_lock;
 mod rw_lock;
 mod unique;
 
+#[cfg(all(any(test, feature = "std"), target_os = "linux"))]
+pub use futex::futex_wait;
+#[cfg(all(any(test, feature = "std"), target_os = "linux"))]
+pub use futex::futex_wake;
+#[cfg(any(test, feature = "std"))]
+pub(crate) use index_lock::increment_yield_counter;
 pub use index_lock::IndexSpinlock;
 pub use index_lock::IndexSpinlockGuard;
 pub use index_lock::Spinlock;
 pub use index_lock::SpinlockGuard;
 pub use rw_lock::RWSpinLock;
+pub use rw_lock::RawRWSpinLock;
 pub use rw_lock::RWSpinReadGuard;
 pub use rw_lock::RWSpinWriteGuard;
 pub use unique::Unique;
@@ -1,35 +1,39 @@
+#[cfg(any(test, feature = "std"))]
+use crate::sync::increment_yield_counter;
 use core::cell::UnsafeCell;
 use core::ops::Deref;
 use core::ops::DerefMut;
 use core::sync::atomic::AtomicU32;
 use core::sync::atomic::Ordering;
 
-#[repr(C)]
+/// The lock word behind RWSpinLock, for locks kept apart from the data they guard - such as one per slot of a buffer.
+/// It holds a reader count, with writers flagging a request to hold off new readers.  A zeroed RawRWSpinLock is unlocked.
+#[repr(transparent)]
 #[derive(Debug)]
-pub struct RWSpinLock<T> {
-    data: UnsafeCell<T>,
+pub struct RawRWSpinLock {
     lock: AtomicU32,
 }
-unsafe impl<T: Send> Send for RWSpinLock<T> {}
-unsafe impl<T: Sync + Send> Sync for RWSpinLock<T> {}
 
-impl<T> RWSpinLock<T> {
+impl RawRWSpinLock {
     const WRITE_LOCK: u32 = 1 << 31;
     const WRITE_REQUEST: u32 = 1 << 30;
-    // const WRITE_MASK : u32 = RWSpinLock::<T>::WRITE_LOCK | RWSpinLock::<T>::WRITE_REQUEST;
+    // const WRITE_MASK : u32 = RawRWSpinLock::WRITE_LOCK | RawRWSpinLock::WRITE_REQUEST;
 
     #[inline]
-    pub const fn new(data: T) -> RWSpinLock<T> {
-        return RWSpinLock {
+    pub const fn new() -> RawRWSpinLock {
+        return RawRWSpinLock {
             lock: AtomicU32::new(0),
-            data: UnsafeCell::new(data),
         };
     }
+
+    /// Wait for shared access.  Every call must be paired with unlock_read.
     #[inline]
-    pub fn read(&self) -> RWSpinReadGuard<T> {
+    pub fn lock_read(&self) {
+        #[cfg(any(test, feature = "std"))]
+        let mut counter = 0;
         let mut lock_value = self.lock.load(Ordering::Acquire);
         loop {
-            if lock_value < RWSpinLock::<T>::WRITE_REQUEST {
+            if lock_value < RawRWSpinLock::WRITE_REQUEST {
                 let target = lock_value + 1;
                 match self.lock.compare_exchange_weak(
                     lock_value,
@@ -37,132 +41,171 @@ impl<T> RWSpinLock<T> {
                     Ordering::Acquire,
                     Ordering::Acquire,
                 ) {
-                    Ok(_) => {
-                        return RWSpinReadGuard {
-                            lock: self,
-                            // data: unsafe { &*self.data.get() },
-                        };
-                    }
+                    Ok(_) => return,
                     Err(x) => lock_value = x,
                 }
             } else {
+                #[cfg(any(test, feature = "std"))]
+                {
+                    counter = increment_yield_counter(counter, 2);
+                }
                 lock_value = self.lock.load(Ordering::Acquire);
             }
-            core::sync::atomic::spin_loop_hint();
+            core::hint::spin_loop();
+        }
+    }
+
+    #[inline]
+    pub fn try_lock_read(&self) -> bool {
+        let lock_value = self.lock.load(Ordering::Acquire);
+        if lock_value < RawRWSpinLock::WRITE_REQUEST {
+            let target = lock_value + 1;
+            return self
+                .lock
+                .compare_exchange(lock_value, target, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok();
         }
+        return false;
     }
+
     #[inline]
-    pub fn write(&self) -> RWSpinWriteGuard<T> {
-        let mut lock_value = self
-            .lock
-            .fetch_or(RWSpinLock::<T>::WRITE_REQUEST, Ordering::Acquire);
+    pub fn unlock_read(&self) {
+        self.lock.fetch_sub(1, Ordering::Release);
+    }
+
+    /// Wait for exclusive access.  Pending writers keep new readers out.  Every call must be paired with unlock_write.
+    #[inline]
+    pub fn lock_write(&self) {
+        #[cfg(any(test, feature = "std"))]
+        let mut counter = 0;
         loop {
-            if lock_value == RWSpinLock::<T>::WRITE_REQUEST {
-                let target = RWSpinLock::<T>::WRITE_LOCK;
-                match self.lock.compare_exchange_weak(
-                    lock_value,
-                    target,
-                    Ordering::Acquire,
-                    Ordering::Relaxed,
-                ) {
-                    Ok(_) => {
-                        return RWSpinWriteGuard {
-                            lock: self,
-                            // data: unsafe { &mut *self.data.get() },
-                        };
-                    }
-                    Err(_x) => {}
-                }
-            } else {
-                core::sync::atomic::spin_loop_hint();
-            }
             // We must continually request, because a write lock will clear all write flags on release
-            lock_value = self
+            let lock_value = self
                 .lock
-                .fetch_or(RWSpinLock::<T>::WRITE_REQUEST, Ordering::Acquire);
+                .fetch_or(RawRWSpinLock::WRITE_REQUEST, Ordering::Acquire);
+            if lock_value & !RawRWSpinLock::WRITE_REQUEST == 0
+                && self
+                    .lock
+                    .compare_exchange_weak(
+                        RawRWSpinLock::WRITE_REQUEST,
+                        RawRWSpinLock::WRITE_LOCK,
+                        Ordering::Acquire,
+                        Ordering::Relaxed,
+                    )
+                    .is_ok()
+            {
+                return;
+            }
+            #[cfg(any(test, feature = "std"))]
+            {
+                counter = increment_yield_counter(counter, 2);
+            }
+            core::hint::spin_loop();
         }
     }
+
     #[inline]
-    pub fn try_read(&self) -> Option<RWSpinReadGuard<T>> {
-        let lock_value = self.lock.load(Ordering::Acquire);
-        if lock_value < RWSpinLock::<T>::WRITE_REQUEST {
-            let target = lock_value + 1;
-            match self.lock.compare_exchange(
-                lock_value,
-                target,
+    pub fn try_lock_write(&self) -> bool {
+        return self
+            .lock
+            .compare_exchange(
+                RawRWSpinLock::WRITE_REQUEST,
+                RawRWSpinLock::WRITE_LOCK,
                 Ordering::Acquire,
                 Ordering::Relaxed,
-            ) {
-                Ok(_) => {
-                    return Some(RWSpinReadGuard {
-                        lock: self,
-                        // data: unsafe { &*self.data.get() },
-                    });
-                }
-                Err(_x) => return None,
-            }
-        }
-        return None;
+            )
+            .is_ok();
+    }
+
+    #[inline]
+    pub fn unlock_write(&self) {
+        self.lock.store(0, Ordering::Release);
+        //self.lock.fetch_and(!RawRWSpinLock::WRITE_MASK, Ordering::SeqCst);
+    }
+
+    /// Trade a read lock for a write lock.  The write request goes in before the read lock is released, so new readers cannot slip in between.
+    #[inline]
+    pub fn upgrade(&self) {
+        self.mark_write_request();
+        self.unlock_read();
+        self.lock_write();
     }
 
     #[inline]
     pub fn mark_write_request(&self) {
         self.lock
-            .fetch_or(RWSpinLock::<T>::WRITE_REQUEST, Ordering::Acquire);
+            .fetch_or(RawRWSpinLock::WRITE_REQUEST, Ordering::Acquire);
     }
+
     #[inline]
     pub fn unmark_write_request(&self) {
         self.lock
-            .fetch_and(!RWSpinLock::<T>::WRITE_REQUEST, Ordering::Release);
+            .fetch_and(!RawRWSpinLock::WRITE_REQUEST, Ordering::Release);
+    }
+}
+
+impl Default for RawRWSpinLock {
+    fn default() -> RawRWSpinLock {
+        return RawRWSpinLock::new();
+    }
+}
+
+#[repr(C)]
+#[derive(Debug)]
+pub struct RWSpinLock<T> {
+    data: UnsafeCell<T>,
+    lock: RawRWSpinLock,
+}
+unsafe impl<T: Send> Send for RWSpinLock<T> {}
+unsafe impl<T: Sync + Send> Sync for RWSpinLock<T> {}
+
+impl<T> RWSpinLock<T> {
+    #[inline]
+    pub const fn new(data: T) -> RWSpinLock<T> {
+        return RWSpinLock {
+            lock: RawRWSpinLock::new(),
+            data: UnsafeCell::new(data),
+        };
+    }
+    #[inline]
+    pub fn read(&self) -> RWSpinReadGuard<T> {
+        self.lock.lock_read();
+        return RWSpinReadGuard { lock: self };
+    }
+    #[inline]
+    pub fn write(&self) -> RWSpinWriteGuard<T> {
+        self.lock.lock_write();
+        return RWSpinWriteGuard { lock: self };
+    }
+    #[inline]
+    pub fn try_read(&self) -> Option<RWSpinReadGuard<T>> {
+        if self.lock.try_lock_read() {
+            return Some(RWSpinReadGuard { lock: self });
+        }
+        return None;
+    }
+
+    #[inline]
+    pub fn mark_write_request(&self) {
+        self.lock.mark_write_request();
+    }
+    #[inline]
+    pub fn unmark_write_request(&self) {
+        self.lock.unmark_write_request();
     }
     #[inline]
     pub fn try_write(&self) -> Option<RWSpinWriteGuard<T>> {
-        match self.lock.compare_exchange(
-            RWSpinLock::<T>::WRITE_REQUEST,
-            RWSpinLock::<T>::WRITE_LOCK,
-            Ordering::Acquire,
-            Ordering::Relaxed,
-        ) {
-            Ok(_) => {
-                return Some(RWSpinWriteGuard {
-                    lock: self,
-                    // data: unsafe { &mut *self.data.get() },
-                });
-            }
-            Err(_x) => return None,
+        if self.lock.try_lock_write() {
+            return Some(RWSpinWriteGuard { lock: self });
         }
+        return None;
     }
     #[inline]
     pub fn upgrade(&self, read: RWSpinReadGuard<T>) -> RWSpinWriteGuard<T> {
-        let mut lock_value = self
-            .lock
-            .fetch_or(RWSpinLock::<T>::WRITE_REQUEST, Ordering::Acquire);
-        core::mem::drop(read);
-        loop {
-            if lock_value == RWSpinLock::<T>::WRITE_REQUEST {
-                let target = RWSpinLock::<T>::WRITE_LOCK;
-                match self.lock.compare_exchange_weak(
-                    lock_value,
-                    target,
-                    Ordering::Acquire,
-                    Ordering::Relaxed,
-                ) {
-                    Ok(_) => {
-                        return RWSpinWriteGuard {
-                            lock: self,
-                            // data: unsafe { &mut *self.data.get() },
-                        };
-                    }
-                    Err(_x) => {}
-                }
-            } else {
-                core::sync::atomic::spin_loop_hint();
-            }
-            // We must continually request, because a write lock will clear all write flags on release
-            lock_value = self
-                .lock
-                .fetch_or(RWSpinLock::<T>::WRITE_REQUEST, Ordering::Acquire);
-        }
+        // The raw lock releases the read lock itself.
+        core::mem::forget(read);
+        self.lock.upgrade();
+        return RWSpinWriteGuard { lock: self };
     }
 
     /// Since this call borrows the Lock mutably, no actual locking needs to take place --
@@ -184,7 +227,7 @@ pub struct RWSpinReadGuard<'a, T: 'a> {
 impl<'a, T> Drop for RWSpinReadGuard<'a, T> {
     fn drop(&mut self) {
         //println!("dropped read");
-        self.lock.lock.fetch_sub(1, Ordering::Release);
+        self.lock.lock.unlock_read();
     }
 }
 impl<'a, T> Deref for RWSpinReadGuard<'a, T> {
@@ -201,8 +244,7 @@ pub struct RWSpinWriteGuard<'a, T: 'a> {
 impl<'a, T> Drop for RWSpinWriteGuard<'a, T> {
     fn drop(&mut self) {
         //println!("dropped write");
-        self.lock.lock.store(0, Ordering::Release);
-        //self.lock.fetch_and(!RWSpinLock::<T>::WRITE_MASK, Ordering::SeqCst);
+        self.lock.lock.unlock_write();
     }
 }
 impl<'a, T> Deref for RWSpinWriteGuard<'a, T> {